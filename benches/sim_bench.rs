@@ -1,5 +1,5 @@
 use criterion::{criterion_group, criterion_main, Criterion};
-use hunllef::{Hunllef, Levels, Player, Prayer, Setup, Weapon};
+use hunllef::{Hunllef, Levels, Player, Prayer, Setup, Trials, Weapon};
 
 fn criterion_benchmark(c: &mut Criterion) {
     let levels = Levels {
@@ -19,8 +19,15 @@ fn criterion_benchmark(c: &mut Criterion) {
 
     let hunllef = Hunllef::new(1);
 
+    let trials = Trials {
+        count: 10_000,
+        seed: 0,
+        threads: 1,
+        precision: None,
+    };
+
     c.bench_function("10k basic", |b| {
-        b.iter(|| hunllef::run_simulation(10_000, &player, &hunllef, 50, false, 6000))
+        b.iter(|| hunllef::run_simulation(&trials, &player, &hunllef, 50, false, 6000))
     });
 }
 
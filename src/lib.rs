@@ -1,14 +1,26 @@
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
 use clap::ValueEnum;
 use fastrand::Rng;
+use hdrhistogram::Histogram;
+use serde::{Deserialize, Serialize};
+
+pub use tables::{ArmourStats, BossStats, PrayerStats, StatTables, WeaponStats};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+mod tables;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum Weapon {
     Bow,
     Staff,
     Halberd,
 }
 
-#[derive(Debug, Clone, Copy, ValueEnum)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum Prayer {
     Rigour,
     Augury,
@@ -17,8 +29,19 @@ pub enum Prayer {
     MysticMight,
 }
 
+/// How the player alternates setups around Hunllef's protection prayer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Rotation {
+    /// Six attacks with each setup
+    SixSix,
+    /// Five attacks with the first setup and one with the second, which is
+    /// enough to make Hunllef switch its prayer away from the first
+    FiveOne,
+}
+
 #[allow(unused)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct Levels {
     pub attack: u8,
     pub strength: u8,
@@ -33,13 +56,17 @@ pub struct Levels {
 pub struct Setup {
     weapon: Weapon,
     attack_delay: u8,
+    hit_delay: u8, //ticks from attacking to the hit landing
     max_hit: u16,
-    acc_roll: u16,
-    rdr: u16, //ranged defensive roll
-    mdr: u16, //magic defensive roll
+    acc_roll: u32,
+    rdr: u32,          //ranged defensive roll
+    mdr: u32,          //magic defensive roll
+    prayer_drain: u16, //drain effect of active prayers per tick
+    prayer_bonus: u16,
 }
 
 impl Setup {
+    /// Setup with the bundled stat tables
     pub fn new(
         weapon: Weapon,
         weapon_tier: u8,
@@ -47,38 +74,35 @@ impl Setup {
         levels: &Levels,
         armour_tier: u8,
     ) -> Setup {
-        fn effective_level(level: u8, prayer_bonus: u8, stance_bonus: u8) -> u16 {
-            (level as u16) * (100 + prayer_bonus as u16) / 100 + 8 + stance_bonus as u16
-        }
+        Setup::from_tables(
+            StatTables::bundled(),
+            weapon,
+            weapon_tier,
+            prayer,
+            levels,
+            armour_tier,
+        )
+    }
 
-        let (armour_acc, armour_def) = match armour_tier {
-            1 => (16, 166),
-            2 => (28, 224),
-            3 => (40, 284),
-            _ => (0, 0),
-        };
+    pub fn from_tables(
+        tables: &StatTables,
+        weapon: Weapon,
+        weapon_tier: u8,
+        prayer: Prayer,
+        levels: &Levels,
+        armour_tier: u8,
+    ) -> Setup {
+        //in u32, as stat overrides can take the rolls past u16
+        fn effective_level(level: u8, prayer_bonus: u16, stance_bonus: u32) -> u32 {
+            level as u32 * (100 + prayer_bonus as u32) / 100 + 8 + stance_bonus
+        }
 
+        let armour = tables.armour(armour_tier);
+        let weapon_stats = tables.weapon(weapon, weapon_tier);
+        let prayer = tables.prayer(prayer);
+        let eq_acc = armour.accuracy as u32 + weapon_stats.accuracy as u32;
         //for the staff, eq_str == max_hit
-        let (weapon_acc, eq_str) = match (weapon, weapon_tier) {
-            (Weapon::Bow, 3) => (172, 138),
-            (Weapon::Staff, 3) => (184, 39),
-            (Weapon::Halberd, 3) => (166, 138),
-            (Weapon::Bow, 2) => (118, 88),
-            (Weapon::Staff, 2) => (128, 31),
-            (Weapon::Halberd, 2) => (114, 88),
-            (Weapon::Bow, 1) => (72, 42),
-            (Weapon::Staff, 1) => (84, 23),
-            (Weapon::Halberd, 1) => (68, 42),
-            (_, _) => panic!("weapon_tier must be 1, 2, or 3"),
-        };
-        let eq_acc = armour_acc + weapon_acc;
-        let (prayer_acc, prayer_str, prayer_def, prayer_def_magic) = match prayer {
-            Prayer::Rigour => (20, 23, 25, 0),
-            Prayer::Augury => (25, 0, 25, 25),
-            Prayer::Piety => (20, 23, 25, 0),
-            Prayer::EagleEye => (15, 15, 15, 0),
-            Prayer::MysticMight => (15, 0, 15, 15),
-        };
+        let eq_str = weapon_stats.strength as u32;
 
         let (acc_lvl, dam_lvl) = match weapon {
             Weapon::Bow => (levels.ranged, levels.ranged),
@@ -87,94 +111,271 @@ impl Setup {
         };
 
         let stance_bonus = if weapon == Weapon::Staff { 3 } else { 0 };
-        let eff_acc_lvl = effective_level(acc_lvl, prayer_acc, stance_bonus);
+        let eff_acc_lvl = effective_level(acc_lvl, prayer.accuracy, stance_bonus);
         let acc_roll = eff_acc_lvl * (eq_acc + 64);
 
         let stance_bonus = if weapon == Weapon::Halberd { 3 } else { 0 };
-        let eff_str_lvl = effective_level(dam_lvl, prayer_str, stance_bonus);
+        let eff_str_lvl = effective_level(dam_lvl, prayer.strength, stance_bonus);
         let max_hit = match weapon {
             Weapon::Bow | Weapon::Halberd => (eff_str_lvl * (eq_str + 64) + 320) / 640,
             Weapon::Staff => eq_str,
         };
+        let max_hit = u16::try_from(max_hit).unwrap_or(u16::MAX);
 
-        let eff_def_lvl = effective_level(levels.defence, prayer_def, 0);
-        let rdr = eff_def_lvl * (armour_def + 64);
+        let eff_def_lvl = effective_level(levels.defence, prayer.defence, 0);
+        let rdr = eff_def_lvl * (armour.defence as u32 + 64);
 
         let stance_bonus = if weapon == Weapon::Staff { 3 } else { 0 };
-        let eff_magic_lvl = effective_level(levels.magic, prayer_def_magic, stance_bonus);
+        let eff_magic_lvl = effective_level(levels.magic, prayer.magic_defence, stance_bonus);
 
         let eff_magic_def_lvl = eff_def_lvl * 3 / 10 + eff_magic_lvl * 7 / 10;
-        let mdr = eff_magic_def_lvl * (armour_def + 64);
+        let mdr = eff_magic_def_lvl * (armour.defence as u32 + 64);
+
+        //projectiles take longer to land, assuming the usual 4-6 tile distance
+        let hit_delay = match weapon {
+            Weapon::Bow => 2,
+            Weapon::Staff => 3,
+            Weapon::Halberd => 1,
+        };
 
         Setup {
             weapon,
             attack_delay: 4,
+            hit_delay,
             max_hit,
             acc_roll,
             rdr,
             mdr,
+            prayer_drain: prayer.drain_effect,
+            prayer_bonus: armour.prayer_bonus,
         }
     }
 
-    fn attack(self, rng: &Rng, hunllef_defensive_roll: u16) -> u16 {
-        //ranges are not inclusive of top, but the rolls need to be
-        if rng.u16(0..self.acc_roll + 1) > rng.u16(0..hunllef_defensive_roll + 1) {
-            rng.u16(0..self.max_hit + 1)
+    fn attack(self, rng: &Rng, hunllef_defensive_roll: u32) -> Hit {
+        Hit::roll(rng, self.acc_roll, hunllef_defensive_roll, self.max_hit)
+    }
+}
+
+/// The outcome of one attack, including the accuracy and defence rolls
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Hit {
+    pub roll: u32,     //attacker's accuracy roll
+    pub def_roll: u32, //defender's defence roll
+    pub damage: u16,
+}
+
+impl Hit {
+    #[inline(always)]
+    fn roll(rng: &Rng, acc_roll: u32, def_roll: u32, max_hit: u16) -> Hit {
+        let roll = rng.u32(0..=acc_roll);
+        let def_roll = rng.u32(0..=def_roll);
+        let damage = if roll > def_roll {
+            rng.u16(0..max_hit + 1)
         } else {
             0
+        };
+        Hit {
+            roll,
+            def_roll,
+            damage,
         }
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-enum AttackStyle {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AttackStyle {
     Ranged,
     Magic,
 }
 
+impl AttackStyle {
+    //ticks for Hunllef's projectiles to reach the player
+    fn hit_delay(self) -> u16 {
+        match self {
+            AttackStyle::Ranged => 2,
+            AttackStyle::Magic => 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum HunllefAttack {
+    Standard {
+        style: AttackStyle,
+        hit: Hit,
+        off_prayer: bool,
+    },
+    Stomp {
+        damage: u16,
+    },
+    Tornado {
+        clipped: Option<u16>,
+    }, //damage if the player is clipped
+}
+
+/// How often Hunllef corrupts the floor and how much standing on it hurts
+#[derive(Debug, Clone, Copy)]
+pub struct FloorTiles {
+    pub intervals: [u16; 3], //ticks between corruptions above 2/3, above 1/3 and below 1/3 hp
+    pub duration: u16,       //ticks each corruption lasts
+    pub damage: u16,         //per tick stood on a corrupted tile
+}
+
+impl Default for FloorTiles {
+    fn default() -> Self {
+        FloorTiles {
+            intervals: [40, 30, 20],
+            duration: 8,
+            damage: 15,
+        }
+    }
+}
+
+/// Which Hunllef is fought: the Corrupted Gauntlet's or the regular one's
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BossVariant {
+    #[default]
+    Corrupted,
+    Crystalline,
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Hunllef {
     hp: u16,
-    max_hit: u16,
+    max_hp: u16,
+    max_hit: u16,            //through protection prayer
+    off_prayer_max_hit: u16, //when the player is on the wrong prayer
+    stomp_max_hit: u16,      //when the player is under Hunllef
+    tornado_damage: (u16, u16),
     attack_delay: u8,    //ticks
-    acc_roll: u16,       //same for ranged and magic
-    defensive_roll: u16, //same for all styles
+    acc_roll: u32,       //same for ranged and magic
+    defensive_roll: u32, //same for all styles
     tornado_cd: u8,      //number of attacks until a tornado attack
     attack_cd: u8,       //ticks until next attack
     style: AttackStyle,
-    attacks_left: u8, //before switching styles
+    attacks_left: u8,        //before switching styles
+    protected: Weapon,       //style of protection prayer
+    prayer_attacks_left: u8, //player attacks before switching prayer
+    floor_tiles: FloorTiles,
+    floor_cd: u16,     //ticks until the floor is next corrupted
+    floor_active: u16, //ticks the current corruption has left
 }
 
 impl Hunllef {
+    /// Corrupted Hunllef
     pub fn new(armour_tier: u8) -> Hunllef {
-        let max_hit = match armour_tier {
-            1 => 13,
-            2 => 10,
-            3 => 8,
-            _ => 0,
-        };
+        Hunllef::for_variant(BossVariant::Corrupted, armour_tier)
+    }
+
+    /// Hunllef with the bundled stat tables
+    pub fn for_variant(variant: BossVariant, armour_tier: u8) -> Hunllef {
+        Hunllef::from_tables(StatTables::bundled(), variant, armour_tier)
+    }
+
+    //Crystal equipment has the same stats as its corrupted counterpart, so
+    //setups are the same for either variant
+    pub fn from_tables(tables: &StatTables, variant: BossVariant, armour_tier: u8) -> Hunllef {
+        let stats = tables.boss(variant);
+        let hp = stats.hp;
+        let max_hit = stats
+            .max_hit
+            .get(armour_tier as usize)
+            .copied()
+            .unwrap_or(0);
+        let off_prayer_max_hit = stats.off_prayer_max_hit;
+        let stomp_max_hit = stats.stomp_max_hit;
+        let tornado_damage = (15, 30);
         let attack_delay = 5;
-        let acc_roll = (240 + 9) * (90 + 64);
-        let defensive_roll = (240 + 9) * (20 + 64);
-        let hp = 1000;
+        let level = stats.level as u32 + 9;
+        let acc_roll = level * (stats.attack_bonus as u32 + 64);
+        let defensive_roll = level * (stats.defence_bonus as u32 + 64);
         let tornado_cd = 12;
         let attack_cd = 0;
         let style = AttackStyle::Ranged;
         let attacks_left = 4;
+        let protected = Weapon::Bow;
+        let prayer_attacks_left = 6;
+        let floor_tiles = FloorTiles::default();
+        let floor_cd = floor_tiles.intervals[0];
+        let floor_active = 0;
 
         Hunllef {
             max_hit,
+            off_prayer_max_hit,
+            stomp_max_hit,
+            tornado_damage,
             attack_delay,
             acc_roll,
             defensive_roll,
             hp,
+            max_hp: hp,
             tornado_cd,
             attack_cd,
             style,
             attacks_left,
+            protected,
+            prayer_attacks_left,
+            floor_tiles,
+            floor_cd,
+            floor_active,
+        }
+    }
+
+    //Hunllef switches its protection prayer after every 6 player attacks to
+    //protect against the style of the 6th. Attacks into its prayer still
+    //count, which is what lets 5:1 keep it off the first setup. Returns
+    //whether the prayer changed.
+    fn take_attack(&mut self, weapon: Weapon) -> bool {
+        self.prayer_attacks_left -= 1;
+        if self.prayer_attacks_left == 0 {
+            self.prayer_attacks_left = 6;
+            let switched = self.protected != weapon;
+            self.protected = weapon;
+            switched
+        } else {
+            false
         }
     }
 
+    /// Sets the range of damage a tornado deals when it clips the player.
+    /// Panics if `min` is above `max`.
+    pub fn with_tornado_damage(mut self, min: u16, max: u16) -> Self {
+        assert!(min <= max, "tornado damage {min}-{max} is an empty range");
+        self.tornado_damage = (min, max);
+        self
+    }
+
+    /// Sets the timing and damage of corrupted floor tiles
+    pub fn with_floor_tiles(mut self, floor_tiles: FloorTiles) -> Self {
+        self.floor_tiles = floor_tiles;
+        self.floor_cd = floor_tiles.intervals[0];
+        self
+    }
+
+    //Counts down to the next floor corruption, which comes around faster as
+    //Hunllef loses hp. Returns whether a new corruption started this tick.
+    fn corrupt_floor(&mut self) -> bool {
+        self.floor_active = self.floor_active.saturating_sub(1);
+        if self.floor_cd > 0 {
+            self.floor_cd -= 1;
+            return false;
+        }
+
+        let (hp, max_hp) = (self.hp as u32, self.max_hp as u32);
+        let phase = if hp * 3 > max_hp * 2 {
+            0
+        } else if hp * 3 > max_hp {
+            1
+        } else {
+            2
+        };
+        self.floor_cd = self.floor_tiles.intervals[phase];
+        self.floor_active = self.floor_tiles.duration;
+        true
+    }
+
     fn switch_style(&mut self) {
         if let AttackStyle::Ranged = self.style {
             self.style = AttackStyle::Magic;
@@ -183,7 +384,8 @@ impl Hunllef {
         }
     }
 
-    fn attack(&mut self, rng: &Rng, player_rdr: u16, player_mdr: u16) -> Option<u16> {
+    #[inline(always)]
+    fn attack(&mut self, rng: &Rng, player: &Player) -> Option<HunllefAttack> {
         if self.attack_cd == 0 {
             //Hunllef switches between ranged/magic after every 4 attacks. This
             //includes attacks replaced by a tornado.
@@ -198,25 +400,40 @@ impl Hunllef {
             //This is close, but not precisely the same as how tornadoes are
             //actually spawned. The true mechanism is not yet known.
             if self.tornado_cd == 0 {
-                //println!("  tornado!");
                 self.tornado_cd = rng.u8(10..15);
-                return None;
+                let clipped = player.tornado_hit_rate > 0.0 && rng.f64() < player.tornado_hit_rate;
+                let (min, max) = self.tornado_damage;
+                let clipped = clipped.then(|| rng.u16(min..=max));
+                return Some(HunllefAttack::Tornado { clipped });
             } else {
                 self.tornado_cd -= 1;
             }
 
+            //standing under Hunllef gets the player stomped instead
+            if player.stomp_rate > 0.0 && rng.f64() < player.stomp_rate {
+                let damage = rng.u16(0..=self.stomp_max_hit);
+                return Some(HunllefAttack::Stomp { damage });
+            }
+
             let pdr = if let AttackStyle::Ranged = self.style {
-                player_rdr
+                player.current.rdr
             } else {
-                player_mdr
+                player.current.mdr
             };
 
-            //ranges are not inclusive of top, but the rolls need to be
-            if rng.u16(0..self.acc_roll + 1) > rng.u16(0..pdr + 1) {
-                Some(rng.u16(0..self.max_hit + 1))
+            let off_prayer = player.off_prayer_rate > 0.0 && rng.f64() < player.off_prayer_rate;
+            let max_hit = if off_prayer {
+                self.off_prayer_max_hit
             } else {
-                Some(0)
-            }
+                self.max_hit
+            };
+
+            let hit = Hit::roll(rng, self.acc_roll, pdr, max_hit);
+            Some(HunllefAttack::Standard {
+                style: self.style,
+                hit,
+                off_prayer,
+            })
         } else {
             self.attack_cd -= 1;
             None
@@ -231,9 +448,22 @@ pub struct Player<'a, 'b> {
     levels: &'b Levels,
     hp: u16,
     fish: u8,
-    redemption: u8,   //number of times to attempt redemption
-    attack_cd: u8,    //ticks
-    attacks_left: u8, //before switching styles
+    #[cfg_attr(not(feature = "advanced"), allow(dead_code))]
+    redemption: u8, //number of times to attempt redemption
+    attack_cd: u8, //ticks
+    rotation: Rotation,
+    misclick_rate: f64,      //chance of switching setup one attack late
+    off_prayer_rate: f64,    //chance of being on the wrong prayer per attack
+    stomp_rate: f64,         //chance of being under Hunllef per attack
+    tornado_hit_rate: f64,   //chance of being clipped per tornado spawn
+    floor_mistake_rate: f64, //chance of standing on a corrupted tile per tick
+    regen_interval: u16,     //ticks per hp regenerated
+    regen_cd: u16,           //ticks until next regenerating
+    prayer: u16,             //prayer points
+    drain_counter: u16,
+    drain_prayer: bool, //whether prayer points are used up at all
+    egniol_doses: u8,
+    drink_at_prayer: u16,
     current: &'a Setup,
 }
 
@@ -247,7 +477,6 @@ impl<'a, 'b> Player<'a, 'b> {
         lost_ticks: u8,
     ) -> Player<'s, 'l> {
         let attack_cd = lost_ticks;
-        let attacks_left = 6;
         let hp = levels.hp as u16;
         Player {
             setup1,
@@ -257,44 +486,167 @@ impl<'a, 'b> Player<'a, 'b> {
             fish,
             redemption,
             attack_cd,
-            attacks_left,
-            current: if fastrand::bool() { setup1 } else { setup2 },
+            rotation: Rotation::SixSix,
+            misclick_rate: 0.0,
+            off_prayer_rate: 0.0,
+            stomp_rate: 0.0,
+            tornado_hit_rate: 0.0,
+            floor_mistake_rate: 0.0,
+            regen_interval: 100,
+            regen_cd: 100,
+            prayer: levels.prayer as u16,
+            drain_counter: 0,
+            drain_prayer: false,
+            egniol_doses: 0,
+            drink_at_prayer: 0,
+            current: setup1,
         }
     }
 
-    fn switch_setup(mut self) {
-        if self.current.weapon == self.setup1.weapon {
-            self.current = self.setup2;
+    /// Sets how the player alternates setups (6:6 by default)
+    pub fn with_rotation(mut self, rotation: Rotation) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    /// Sets the chance that the player switches setup one attack late,
+    /// attacking into Hunllef's protection prayer
+    pub fn with_misclick_rate(mut self, misclick_rate: f64) -> Self {
+        self.misclick_rate = misclick_rate;
+        self
+    }
+
+    /// Sets the chance that the player is on the wrong protection prayer for
+    /// each of Hunllef's attacks
+    pub fn with_off_prayer_rate(mut self, off_prayer_rate: f64) -> Self {
+        self.off_prayer_rate = off_prayer_rate;
+        self
+    }
+
+    /// Sets the chance that the player is under Hunllef, and gets stomped,
+    /// for each of its attacks
+    pub fn with_stomp_rate(mut self, stomp_rate: f64) -> Self {
+        self.stomp_rate = stomp_rate;
+        self
+    }
+
+    /// Sets the chance that the player is clipped by each set of tornadoes
+    pub fn with_tornado_hit_rate(mut self, tornado_hit_rate: f64) -> Self {
+        self.tornado_hit_rate = tornado_hit_rate;
+        self
+    }
+
+    /// Sets the chance that the player stands on a corrupted floor tile for
+    /// each tick the floor is corrupted
+    pub fn with_floor_mistake_rate(mut self, floor_mistake_rate: f64) -> Self {
+        self.floor_mistake_rate = floor_mistake_rate;
+        self
+    }
+
+    /// Sets whether the player uses a rapid heal style effect, which doubles
+    /// natural hp regeneration to 1 hp every 50 ticks
+    pub fn with_rapid_heal(mut self, rapid_heal: bool) -> Self {
+        self.regen_interval = if rapid_heal { 50 } else { 100 };
+        self.regen_cd = self.regen_interval;
+        self
+    }
+
+    /// Drains prayer points for the active prayers, with running out counted
+    /// as a failure. Egniol doses are drunk when prayer drops below
+    /// `drink_at_prayer`.
+    pub fn with_prayer_drain(mut self, egniol_doses: u8, drink_at_prayer: u16) -> Self {
+        self.drain_prayer = true;
+        self.egniol_doses = egniol_doses;
+        self.drink_at_prayer = drink_at_prayer;
+        self
+    }
+
+    //The player watches Hunllef's overhead and counts the attacks until it
+    //next switches prayer
+    #[inline(always)]
+    fn choose_setup(&self, hunllef: &Hunllef) -> &'a Setup {
+        let other = if self.current.weapon == self.setup1.weapon {
+            self.setup2
         } else {
-            self.current = self.setup1;
+            self.setup1
+        };
+        match self.rotation {
+            Rotation::SixSix if hunllef.protected == self.current.weapon => other,
+            Rotation::SixSix => self.current,
+            Rotation::FiveOne
+                if hunllef.protected == self.setup1.weapon || hunllef.prayer_attacks_left == 1 =>
+            {
+                self.setup2
+            }
+            Rotation::FiveOne => self.setup1,
         }
     }
 
-    fn attack(&mut self, rng: &Rng, hunllef_defensive_roll: u16) -> Option<u16> {
+    #[inline(always)]
+    fn attack(&mut self, rng: &Rng, hunllef: &Hunllef) -> Option<Hit> {
         if self.attack_cd == 0 {
-            //dbg!(self);
-            if self.attacks_left == 0 {
-                self.switch_setup();
-                self.attacks_left = 6;
+            let setup = self.choose_setup(hunllef);
+            let late = self.misclick_rate > 0.0 && rng.f64() < self.misclick_rate;
+            if setup.weapon != self.current.weapon && !late {
+                self.current = setup;
             }
             let setup = self.current;
 
             self.attack_cd += setup.attack_delay - 1; //first tick of delay is
                                                       //the attack
-            self.attacks_left -= 1;
-            Some(setup.attack(rng, hunllef_defensive_roll))
+            let mut hit = setup.attack(rng, hunllef.defensive_roll);
+            if setup.weapon == hunllef.protected {
+                hit.damage = 0;
+            }
+            Some(hit)
         } else {
             self.attack_cd -= 1;
             None
         }
     }
 
+    //Natural regeneration every regen_interval ticks, up to the player's hp
+    //level. Called every tick, and counts down rather than dividing the time.
+    //Returns whether any hp was regenerated.
+    fn regenerate(&mut self) -> bool {
+        if self.regen_cd > 0 {
+            self.regen_cd -= 1;
+            return false;
+        }
+        self.regen_cd = self.regen_interval - 1;
+        if self.hp < self.levels.hp as u16 {
+            self.hp += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    //Every tick the drain counter goes up by the drain effect of the active
+    //prayers, and a point is lost each time it passes the resistance
+    fn use_prayer(&mut self) {
+        self.drain_counter += self.current.prayer_drain;
+        let resistance = 60 + 2 * self.current.prayer_bonus;
+        while self.drain_counter > resistance {
+            self.drain_counter -= resistance;
+            self.prayer = self.prayer.saturating_sub(1);
+        }
+    }
+
+    fn drink_egniol(&mut self) {
+        if self.egniol_doses > 0 {
+            self.egniol_doses -= 1;
+            self.attack_cd += 3;
+            let level = self.levels.prayer as u16;
+            self.prayer = (self.prayer + 7 + level / 4).min(level);
+        }
+    }
+
     fn eat_fish(&mut self) {
         if self.fish > 0 {
             self.fish -= 1;
             self.attack_cd += 3;
             self.hp += 20;
-            //println!("  eating a fish to take us to {}", self.hp);
         }
     }
 
@@ -302,151 +654,1053 @@ impl<'a, 'b> Player<'a, 'b> {
         if self.redemption > 0 {
             self.redemption -= 1;
             self.hp += self.levels.prayer as u16 / 4;
-            //println!("  redemption healing to take us to {}", self.hp);
         }
     }
 }
 
-pub fn run_simulation(
-    trials: u32,
+/// Something that happened during a fight. Emitted by `fight` for every tick
+/// it happens on, which `trace_fights` collects into a log. Attacks are
+/// reported on the tick they land.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    PlayerAttack {
+        weapon: Weapon,
+        hit: Hit,
+        into_prayer: bool,
+        hunllef_hp: u16, //after the hit
+    },
+    HunllefPrayer {
+        protected: Weapon,
+    },
+    HunllefAttack {
+        style: AttackStyle,
+        hit: Hit,
+        off_prayer: bool,
+        player_hp: u16, //after the hit
+    },
+    Stomp {
+        damage: u16,
+        player_hp: u16, //after the hit
+    },
+    Tornado,
+    TornadoHit {
+        damage: u16,
+        player_hp: u16, //after the hit
+    },
+    FloorCorrupted,
+    FloorHit {
+        damage: u16,
+        player_hp: u16, //after the hit
+    },
+    FishEaten {
+        hp: u16, //after eating
+        fish_left: u8,
+    },
+    Redemption {
+        hp: u16, //after healing
+    },
+    Regenerated {
+        hp: u16, //after healing
+    },
+    EgniolDrunk {
+        prayer: u16, //after drinking
+        doses_left: u8,
+    },
+    OutOfPrayer,
+    SetupSwitch {
+        weapon: Weapon,
+    },
+    PlayerDied,
+    HunllefDied,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::PlayerAttack {
+                weapon,
+                hit,
+                into_prayer,
+                hunllef_hp,
+            } => write!(
+                f,
+                "player attacks with {weapon:?}{} (roll {} vs {}): {} damage, hunllef at {hunllef_hp} hp",
+                if *into_prayer { " into prayer" } else { "" },
+                hit.roll,
+                hit.def_roll,
+                hit.damage
+            ),
+            Event::HunllefPrayer { protected } => {
+                write!(f, "hunllef switches to protect from {protected:?}")
+            }
+            Event::HunllefAttack {
+                style,
+                hit,
+                off_prayer,
+                player_hp,
+            } => write!(
+                f,
+                "hunllef attacks with {style:?}{} (roll {} vs {}): {} damage, player at {player_hp} hp",
+                if *off_prayer { " off prayer" } else { "" },
+                hit.roll,
+                hit.def_roll,
+                hit.damage
+            ),
+            Event::Stomp { damage, player_hp } => {
+                write!(f, "hunllef stomps: {damage} damage, player at {player_hp} hp")
+            }
+            Event::Tornado => write!(f, "hunllef spawns tornadoes"),
+            Event::TornadoHit { damage, player_hp } => write!(
+                f,
+                "player clipped by a tornado: {damage} damage, player at {player_hp} hp"
+            ),
+            Event::FloorCorrupted => write!(f, "hunllef corrupts the floor"),
+            Event::FloorHit { damage, player_hp } => write!(
+                f,
+                "player stands on a corrupted tile: {damage} damage, player at {player_hp} hp"
+            ),
+            Event::FishEaten { hp, fish_left } => {
+                write!(f, "player eats a fish to {hp} hp ({fish_left} left)")
+            }
+            Event::Redemption { hp } => write!(f, "redemption heals player to {hp} hp"),
+            Event::Regenerated { hp } => write!(f, "player regenerates to {hp} hp"),
+            Event::EgniolDrunk { prayer, doses_left } => write!(
+                f,
+                "player drinks an egniol to {prayer} prayer ({doses_left} doses left)"
+            ),
+            Event::OutOfPrayer => write!(f, "player runs out of prayer"),
+            Event::SetupSwitch { weapon } => write!(f, "player switches to {weapon:?}"),
+            Event::PlayerDied => write!(f, "player dies"),
+            Event::HunllefDied => write!(f, "hunllef dies"),
+        }
+    }
+}
+
+/// The end state of a single fight
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Fight {
+    pub time: u16, //ticks
+    pub fish_eaten: u8,
+    pub fish_left: u8,
+    pub off_prayer_hits: u16, //Hunllef attacks taken on the wrong prayer
+    pub doses_drunk: u8,
+    pub prayer: Option<u16>, //points left, None without prayer drain
+    pub player_hp: u16,
+    pub hunllef_hp: u16,
+}
+
+impl Fight {
+    /// Hunllef dead and player alive with prayer left
+    pub fn success(&self) -> bool {
+        self.player_hp > 0 && self.prayer != Some(0) && self.hunllef_hp == 0
+    }
+
+    /// Why the fight was lost, None if it was a success
+    pub fn failure(&self) -> Option<Failure> {
+        if self.success() {
+            None
+        } else if self.player_hp == 0 && self.fish_left == 0 {
+            Some(Failure::OutOfFish)
+        } else if self.player_hp == 0 {
+            Some(Failure::DiedWithFish)
+        } else if self.prayer == Some(0) {
+            Some(Failure::OutOfPrayer)
+        } else {
+            Some(Failure::MaxTime)
+        }
+    }
+}
+
+/// Cause of a failed fight
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Failure {
+    OutOfFish,
+    DiedWithFish, //killed through the eat threshold with food left
+    OutOfPrayer,
+    MaxTime, //both still alive when the time ran out
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Failure::OutOfFish => write!(f, "died out of fish"),
+            Failure::DiedWithFish => write!(f, "died with fish left"),
+            Failure::OutOfPrayer => write!(f, "out of prayer"),
+            Failure::MaxTime => write!(f, "max time exceeded"),
+        }
+    }
+}
+
+//Fights still going after this many ticks (about 100 minutes) end as
+//running out of time, with or without --max-time, so ones that can never be
+//won (like every attack missing) still stop
+const TICK_CAP: u16 = 10_000;
+
+//Copies the templates for a new fight, with Hunllef protecting against either
+//of the player's setups
+#[inline(always)]
+fn start_fight<'a, 'b>(
+    player: &Player<'a, 'b>,
+    hunllef: &Hunllef,
+    rng: &Rng,
+) -> (Player<'a, 'b>, Hunllef) {
+    let mut player = *player;
+    let mut hunllef = *hunllef;
+    hunllef.protected = if rng.bool() {
+        player.setup1.weapon
+    } else {
+        player.setup2.weapon
+    };
+    player.current = player.choose_setup(&hunllef);
+    (player, hunllef)
+}
+
+fn fight<F: FnMut(u16, Event)>(
     player: &Player,
     hunllef: &Hunllef,
     eat_at_hp: u16,
     _tick_eat: bool,
     _max_time: u16,
-) -> (u32, Vec<u64>, Vec<u16>) {
-    let mut times = Vec::new();
-    let mut fish_rem = Vec::new();
-    let mut success = 0;
-    let rng = fastrand::Rng::new();
+    rng: &Rng,
+    mut log: F,
+) -> Fight {
+    let (mut player, mut hunllef) = start_fight(player, hunllef, rng);
+    let starting_fish = player.fish;
+    let starting_doses = player.egniol_doses;
+    let mut off_prayer_hits = 0;
 
     #[cfg(feature = "advanced")]
     //this ensures that in tick eat sims we don't heal up too much
     let eat_at_hp = if _tick_eat { 0 } else { eat_at_hp };
 
-    // dbg!(&Player);
-
-    for _ in 0..trials {
-        //println!("loop {n}");
-        let mut player = Player::new(
-            player.setup1,
-            player.setup2,
-            player.levels,
-            player.fish,
-            player.redemption,
-            player.attack_cd,
-        );
-        let mut hunllef = *hunllef;
-        let mut time: u16 = 0; //elapsed time for this trial
-
-        while hunllef.hp > 0 && player.hp > 0 {
-            //println!("t={:0>3}, php: {}, hhp: {}", time, player.hp, hunllef.hp);
-            //println!("  {}, {}", player.attack_cd, player.attacks_left);
-            if let Some(damage) = player.attack(&rng, hunllef.defensive_roll) {
-                if hunllef.hp < damage {
-                    hunllef.hp = 0;
-                } else {
-                    hunllef.hp -= damage;
-                }
-                //println!("  hunllef takes {damage} damage");
+    #[cfg(feature = "advanced")]
+    let max_time = _max_time.min(TICK_CAP);
+    #[cfg(not(feature = "advanced"))]
+    let max_time = TICK_CAP;
+
+    //the attack each side has in flight and the tick it lands on, which is
+    //u16::MAX with none. Every hit lands before its attacker's next attack, so
+    //there's at most one each.
+    let mut player_lands = u16::MAX;
+    let mut player_hit = (player.current.weapon, Hit::default(), false);
+    let mut hunllef_lands = u16::MAX;
+    let mut hunllef_hit = HunllefAttack::Tornado { clipped: None };
+
+    let mut time: u16 = 0; //elapsed time for this trial
+                           //checked every tick, but fixed for the whole fight
+    let drain_prayer = player.drain_prayer;
+    let floor = player.floor_mistake_rate > 0.0;
+
+    while hunllef.hp > 0 && player.hp > 0 {
+        //prayer points only run out if they're drained
+        if drain_prayer {
+            player.use_prayer();
+            if player.prayer == 0 {
+                log(time, Event::OutOfPrayer);
+                break;
             }
+        }
 
+        let weapon = player.current.weapon;
+        if let Some(hit) = player.attack(rng, &hunllef) {
             let setup = player.current;
+            if setup.weapon != weapon {
+                log(
+                    time,
+                    Event::SetupSwitch {
+                        weapon: setup.weapon,
+                    },
+                );
+            }
 
-            if let Some(damage) = hunllef.attack(&rng, setup.rdr, setup.mdr) {
-                let _starting_hp = player.hp;
-                if player.hp < damage {
-                    player.hp = 0;
-                } else {
-                    player.hp -= damage;
+            let into_prayer = setup.weapon == hunllef.protected;
+            player_lands = time + setup.hit_delay as u16;
+            player_hit = (setup.weapon, hit, into_prayer);
+
+            if hunllef.take_attack(setup.weapon) {
+                let protected = setup.weapon;
+                log(time, Event::HunllefPrayer { protected });
+            }
+        }
+
+        if let Some(attack) = hunllef.attack(rng, &player) {
+            //only ranged and magic attacks are projectiles
+            hunllef_lands = match attack {
+                HunllefAttack::Standard { style, .. } => time + style.hit_delay(),
+                _ => time,
+            };
+            hunllef_hit = attack;
+        }
+
+        if player_lands == time {
+            let (weapon, hit, into_prayer) = player_hit;
+            hunllef.hp = hunllef.hp.saturating_sub(hit.damage);
+            let hunllef_hp = hunllef.hp;
+            log(
+                time,
+                Event::PlayerAttack {
+                    weapon,
+                    hit,
+                    into_prayer,
+                    hunllef_hp,
+                },
+            );
+            if hunllef.hp == 0 {
+                log(time, Event::HunllefDied);
+            }
+        }
+        //the fight ends on the tick either side dies, before anyone else acts
+        if hunllef.hp == 0 {
+            time += 1;
+            break;
+        }
+
+        if hunllef_lands == time {
+            let attack = hunllef_hit;
+            let _starting_hp = player.hp;
+            let damage = match attack {
+                HunllefAttack::Standard { hit, .. } => hit.damage,
+                HunllefAttack::Stomp { damage } => damage,
+                HunllefAttack::Tornado { clipped } => clipped.unwrap_or(0),
+            };
+            player.hp = player.hp.saturating_sub(damage);
+            let player_hp = player.hp;
+
+            match attack {
+                HunllefAttack::Standard {
+                    style,
+                    hit,
+                    off_prayer,
+                } => {
+                    off_prayer_hits += off_prayer as u16;
+                    log(
+                        time,
+                        Event::HunllefAttack {
+                            style,
+                            hit,
+                            off_prayer,
+                            player_hp,
+                        },
+                    );
                 }
-                //println!("  player takes {} damage", _starting_hp - player.hp);
-
-                //only tick eat/redemption when hunllef is attacking
-                #[cfg(feature = "advanced")]
-                {
-                    if _starting_hp > hunllef.max_hit {
-                        //redemption when hp is under 10% of max hp
-                        //@90 hp be below 9, @91hp be below 10
-                        if player.hp < (player.levels.hp as u16 - 1) / 10 + 1 {
-                            player._redemption_heal();
-                        }
-                    } else if _tick_eat {
-                        player.eat_fish();
-                        //println!("  tick ate from {} to {}", _starting_hp, player.hp);
+                HunllefAttack::Stomp { damage } => log(time, Event::Stomp { damage, player_hp }),
+                HunllefAttack::Tornado { clipped } => {
+                    log(time, Event::Tornado);
+                    if let Some(damage) = clipped {
+                        log(time, Event::TornadoHit { damage, player_hp });
+                    }
+                }
+            }
+
+            //only tick eat/redemption when hunllef is attacking
+            #[cfg(feature = "advanced")]
+            if player.hp > 0 && !matches!(attack, HunllefAttack::Tornado { .. }) {
+                if _starting_hp > hunllef.max_hit {
+                    //redemption when hp is under 10% of max hp
+                    //@90 hp be below 9, @91hp be below 10
+                    if player.hp < (player.levels.hp as u16 - 1) / 10 + 1 && player.redemption > 0 {
+                        player._redemption_heal();
+                        log(time, Event::Redemption { hp: player.hp });
                     }
+                } else if _tick_eat && player.fish > 0 {
+                    player.eat_fish();
+                    let (hp, fish_left) = (player.hp, player.fish);
+                    log(time, Event::FishEaten { hp, fish_left });
                 }
             }
 
-            //TODO: This should probably move under Hunllef attacks. Player HP
-            //can only drop below the threshold after being attacked
-            if player.hp < eat_at_hp {
-                player.eat_fish();
+            if player.hp == 0 {
+                log(time, Event::PlayerDied);
             }
+        }
 
+        if player.hp == 0 {
             time += 1;
+            break;
+        }
 
-            #[cfg(feature = "advanced")]
-            {
-                if time > _max_time {
+        //the floor can only hurt a player who sometimes stands on it
+        if floor {
+            if hunllef.corrupt_floor() {
+                log(time, Event::FloorCorrupted);
+            }
+            if hunllef.floor_active > 0 && rng.f64() < player.floor_mistake_rate {
+                let damage = hunllef.floor_tiles.damage;
+                player.hp = player.hp.saturating_sub(damage);
+                let player_hp = player.hp;
+                log(time, Event::FloorHit { damage, player_hp });
+                if player.hp == 0 {
+                    log(time, Event::PlayerDied);
+                    time += 1;
                     break;
                 }
             }
         }
 
-        fish_rem.push(player.fish); //have the count include failure cases
-        if player.hp > 0 && hunllef.hp == 0 {
-            success += 1;
-            times.push(time);
-            //fish_rem.push(player.fish);
-            //println!("SUCCESS\n");
-        } else {
-            //println!("FAILURE\n");
+        if player.regenerate() {
+            log(time, Event::Regenerated { hp: player.hp });
+        }
+
+        //TODO: This should probably move under Hunllef attacks. Player HP
+        //can only drop below the threshold after being attacked
+        if player.hp < eat_at_hp && player.fish > 0 {
+            player.eat_fish();
+            let (hp, fish_left) = (player.hp, player.fish);
+            log(time, Event::FishEaten { hp, fish_left });
+        }
+
+        if drain_prayer && player.prayer < player.drink_at_prayer && player.egniol_doses > 0 {
+            player.drink_egniol();
+            let (prayer, doses_left) = (player.prayer, player.egniol_doses);
+            log(time, Event::EgniolDrunk { prayer, doses_left });
+        }
+
+        time += 1;
+        if time > max_time {
+            break;
         }
     }
 
-    let fish_eaten: Vec<u64> = fish_rem.iter().map(|n| (player.fish - *n) as u64).collect();
+    Fight {
+        time,
+        fish_eaten: starting_fish - player.fish,
+        fish_left: player.fish,
+        off_prayer_hits,
+        doses_drunk: starting_doses - player.egniol_doses,
+        prayer: player.drain_prayer.then_some(player.prayer),
+        player_hp: player.hp,
+        hunllef_hp: hunllef.hp,
+    }
+}
+
+/// How many trials to run, the seed they're drawn from and how many threads
+/// to run them on
+#[derive(Debug, Clone, Copy)]
+pub struct Trials {
+    pub count: u32, //per batch if running to a precision
+    pub seed: u64,
+    pub threads: usize,
+    pub precision: Option<Precision>,
+}
 
-    (success, fish_eaten, times)
+/// Interval that `Precision` narrows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PrecisionTarget {
+    /// Success rate, with the width as a fraction
+    SuccessRate,
+    /// Mean time of successful trials, with the width in ticks
+    MeanTime,
+}
+
+/// Keeps running batches of trials until the 95% confidence interval on the
+/// target is no wider than `width`, or `max_trials` have been run. Mean time
+/// can't narrow without successes, so hopeless setups run to the cap.
+#[derive(Debug, Clone, Copy)]
+pub struct Precision {
+    pub target: PrecisionTarget,
+    pub width: f64,
+    pub max_trials: u32,
 }
 
-pub fn data_mode(
-    trials: u32,
+impl Precision {
+    fn reached(&self, result: &SimulationResult) -> bool {
+        let width = match self.target {
+            PrecisionTarget::SuccessRate => {
+                let (low, high) = result.success_interval();
+                high - low
+            }
+            PrecisionTarget::MeanTime => 2.0 * Z_95 * result.time_std_error(),
+        };
+        width <= self.width
+    }
+}
+
+//Trials run in chunks, each with its own generator seeded from the master
+//seed, so a seed replays the same fights however many threads there are
+const CHUNK_SIZE: u32 = 1000;
+
+fn chunk_seeds(seed: u64, trials: u32) -> Vec<u64> {
+    let rng = Rng::with_seed(seed);
+    (0..trials.div_ceil(CHUNK_SIZE))
+        .map(|_| rng.u64(..))
+        .collect()
+}
+
+/// Streaming aggregate of one per-trial value: exact count, sum, min and max
+/// alongside an HDR histogram for percentiles, in constant memory
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    count: u64,
+    sum: u64,
+    sum_squares: u128,
+    min: u64,
+    max: u64,
+    histogram: Histogram<u64>,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Stats {
+            count: 0,
+            sum: 0,
+            sum_squares: 0,
+            min: u64::MAX,
+            max: 0,
+            //auto-resizing, so any value can be recorded
+            histogram: Histogram::new(3).unwrap(),
+        }
+    }
+}
+
+impl Stats {
+    pub fn record(&mut self, value: u64) {
+        self.count += 1;
+        self.sum += value;
+        self.sum_squares += value as u128 * value as u128;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.histogram.record(value).unwrap();
+    }
+
+    pub fn merge(&mut self, other: &Stats) {
+        self.count += other.count;
+        self.sum += other.sum;
+        self.sum_squares += other.sum_squares;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.histogram.add(&other.histogram).unwrap();
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn sum(&self) -> u64 {
+        self.sum
+    }
+
+    /// None when nothing has been recorded
+    pub fn min(&self) -> Option<u64> {
+        (self.count > 0).then_some(self.min)
+    }
+
+    /// None when nothing has been recorded
+    pub fn max(&self) -> Option<u64> {
+        (self.count > 0).then_some(self.max)
+    }
+
+    /// NaN when nothing has been recorded
+    pub fn mean(&self) -> f64 {
+        self.sum as f64 / self.count as f64
+    }
+
+    /// Sample variance, NaN with fewer than two values
+    pub fn variance(&self) -> f64 {
+        //exact in integers to avoid cancellation over many trials
+        let n = self.count as u128;
+        let spread = n * self.sum_squares - self.sum as u128 * self.sum as u128;
+        spread as f64 / (n as f64 * (n as f64 - 1.0))
+    }
+
+    /// Standard error of the mean
+    pub fn std_error(&self) -> f64 {
+        (self.variance() / self.count as f64).sqrt()
+    }
+
+    pub fn histogram(&self) -> &Histogram<u64> {
+        &self.histogram
+    }
+}
+
+/// Failed fights by cause, each recording the Hunllef HP left at failure
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Failures {
+    pub out_of_fish: Stats,
+    pub died_with_fish: Stats,
+    pub out_of_prayer: Stats,
+    pub max_time: Stats,
+}
+
+impl Failures {
+    fn record(&mut self, failure: Failure, hunllef_hp: u16) {
+        let cause = match failure {
+            Failure::OutOfFish => &mut self.out_of_fish,
+            Failure::DiedWithFish => &mut self.died_with_fish,
+            Failure::OutOfPrayer => &mut self.out_of_prayer,
+            Failure::MaxTime => &mut self.max_time,
+        };
+        cause.record(hunllef_hp as u64);
+    }
+
+    fn merge(&mut self, other: &Failures) {
+        self.out_of_fish.merge(&other.out_of_fish);
+        self.died_with_fish.merge(&other.died_with_fish);
+        self.out_of_prayer.merge(&other.out_of_prayer);
+        self.max_time.merge(&other.max_time);
+    }
+
+    /// Hunllef HP left in the fights lost to a cause
+    pub fn cause(&self, failure: Failure) -> &Stats {
+        match failure {
+            Failure::OutOfFish => &self.out_of_fish,
+            Failure::DiedWithFish => &self.died_with_fish,
+            Failure::OutOfPrayer => &self.out_of_prayer,
+            Failure::MaxTime => &self.max_time,
+        }
+    }
+
+    pub fn total(&self) -> u32 {
+        (self.out_of_fish.count()
+            + self.died_with_fish.count()
+            + self.out_of_prayer.count()
+            + self.max_time.count()) as u32
+    }
+}
+
+//standard normal quantile for two-sided 95% intervals
+const Z_95: f64 = 1.959_963_984_540_054;
+
+/// Aggregated results of a simulation run
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SimulationResult {
+    pub trials: u32,
+    pub success: u32,
+    pub failures: Failures,
+    pub time: Stats,         //ticks, successful trials only
+    pub fish_eaten: Stats,   //includes failure cases
+    pub success_fish: Stats, //fish eaten, successful trials only
+    pub off_prayer_hits: Stats,
+    pub doses_drunk: Stats,
+}
+
+impl SimulationResult {
+    fn record(&mut self, fight: &Fight) {
+        self.trials += 1;
+        self.fish_eaten.record(fight.fish_eaten as u64);
+        self.off_prayer_hits.record(fight.off_prayer_hits as u64);
+        self.doses_drunk.record(fight.doses_drunk as u64);
+        match fight.failure() {
+            None => {
+                self.success += 1;
+                self.time.record(fight.time as u64);
+                self.success_fish.record(fight.fish_eaten as u64);
+            }
+            Some(failure) => self.failures.record(failure, fight.hunllef_hp),
+        }
+    }
+
+    /// Combines the results of two runs, as if their trials were run together
+    pub fn merge(&mut self, other: &SimulationResult) {
+        self.trials += other.trials;
+        self.success += other.success;
+        self.failures.merge(&other.failures);
+        self.time.merge(&other.time);
+        self.fish_eaten.merge(&other.fish_eaten);
+        self.success_fish.merge(&other.success_fish);
+        self.off_prayer_hits.merge(&other.off_prayer_hits);
+        self.doses_drunk.merge(&other.doses_drunk);
+    }
+
+    /// Fraction of trials that succeeded, from 0 to 1
+    pub fn success_rate(&self) -> f64 {
+        self.success as f64 / self.trials as f64
+    }
+
+    /// 95% Wilson score interval on the success rate, as (low, high)
+    pub fn success_interval(&self) -> (f64, f64) {
+        let n = self.trials as f64;
+        let p = self.success_rate();
+        let z2 = Z_95 * Z_95;
+        let centre = (p + z2 / (2.0 * n)) / (1.0 + z2 / n);
+        let half_width = Z_95 / (1.0 + z2 / n) * (p * (1.0 - p) / n + z2 / (4.0 * n * n)).sqrt();
+        (
+            (centre - half_width).max(0.0),
+            (centre + half_width).min(1.0),
+        )
+    }
+
+    /// Mean kill time in ticks of the successful trials
+    pub fn mean_time(&self) -> f64 {
+        self.time.mean()
+    }
+
+    pub fn time_std_error(&self) -> f64 {
+        self.time.std_error()
+    }
+
+    pub fn median_time(&self) -> u64 {
+        self.time_percentile(50.0)
+    }
+
+    /// Kill time in ticks at a percentile (0-100) of the successful trials
+    pub fn time_percentile(&self, percentile: f64) -> u64 {
+        self.time.histogram().value_at_percentile(percentile)
+    }
+
+    /// Mean fish eaten across all trials
+    pub fn mean_fish(&self) -> f64 {
+        self.fish_eaten.mean()
+    }
+
+    pub fn fish_std_error(&self) -> f64 {
+        self.fish_eaten.std_error()
+    }
+
+    /// Mean fish eaten in the successful trials, which unlike `mean_fish`
+    /// doesn't flatter strategies that die before eating
+    pub fn mean_success_fish(&self) -> f64 {
+        self.success_fish.mean()
+    }
+
+    pub fn median_fish(&self) -> u64 {
+        self.fish_percentile(50.0)
+    }
+
+    /// Fish eaten at a percentile (0-100) of all trials
+    pub fn fish_percentile(&self, percentile: f64) -> u64 {
+        self.fish_eaten.histogram().value_at_percentile(percentile)
+    }
+}
+
+pub fn run_simulation(
+    trials: &Trials,
     player: &Player,
     hunllef: &Hunllef,
     eat_at_hp: u16,
     tick_eat: bool,
     max_time: u16,
-) -> Vec<u32> {
-    let mut success_rate: Vec<u32> = Vec::with_capacity(player.fish as usize);
-    for i in 0..=player.fish {
-        let player = Player::new(
-            player.setup1,
-            player.setup2,
-            player.levels,
-            i,
-            player.redemption,
-            player.attack_cd,
-        );
+) -> SimulationResult {
+    let Some(precision) = trials.precision else {
+        return run_batch(trials, player, hunllef, eat_at_hp, tick_eat, max_time);
+    };
 
-        let (success, _, _) = run_simulation(
-            trials,
-            &player,
-            hunllef,
-            eat_at_hp,
-            tick_eat,
-            max_time,
-        );
+    //each batch is seeded from the master seed, so a seed replays the same
+    //batches and stops at the same point
+    let batch_seeds = Rng::with_seed(trials.seed);
+    let mut result = SimulationResult::default();
+    while result.trials < precision.max_trials {
+        let batch = Trials {
+            count: trials
+                .count
+                .max(1)
+                .min(precision.max_trials - result.trials),
+            seed: batch_seeds.u64(..),
+            ..*trials
+        };
+        result.merge(&run_batch(
+            &batch, player, hunllef, eat_at_hp, tick_eat, max_time,
+        ));
+        if precision.reached(&result) {
+            break;
+        }
+    }
+    result
+}
+
+fn run_batch(
+    trials: &Trials,
+    player: &Player,
+    hunllef: &Hunllef,
+    eat_at_hp: u16,
+    tick_eat: bool,
+    max_time: u16,
+) -> SimulationResult {
+    let seeds = chunk_seeds(trials.seed, trials.count);
+    let next_chunk = AtomicUsize::new(0);
+
+    let run_chunk = |chunk: usize, result: &mut SimulationResult| {
+        let rng = Rng::with_seed(seeds[chunk]);
+        let count = CHUNK_SIZE.min(trials.count - chunk as u32 * CHUNK_SIZE);
+        for _ in 0..count {
+            let fight = fight(
+                player,
+                hunllef,
+                eat_at_hp,
+                tick_eat,
+                max_time,
+                &rng,
+                |_, _| (),
+            );
+            result.record(&fight);
+        }
+    };
+
+    //each worker takes the next chunk until there are none left, recording
+    //into its own result. Merging is exact and order-independent, so the
+    //total doesn't depend on which worker ran which chunk.
+    thread::scope(|s| {
+        let workers: Vec<_> = (0..trials.threads.max(1))
+            .map(|_| {
+                s.spawn(|| {
+                    let mut result = SimulationResult::default();
+                    loop {
+                        let chunk = next_chunk.fetch_add(1, Ordering::Relaxed);
+                        if chunk >= seeds.len() {
+                            break result;
+                        }
+                        run_chunk(chunk, &mut result);
+                    }
+                })
+            })
+            .collect();
+
+        let mut result = SimulationResult::default();
+        for worker in workers {
+            result.merge(&worker.join().unwrap());
+        }
+        result
+    })
+}
 
-        success_rate.push(success);
+/// Runs the first `fights` trials that `run_simulation` would run with the
+/// same seed, returning each result with its tick-by-tick event log.
+pub fn trace_fights(
+    fights: u32,
+    player: &Player,
+    hunllef: &Hunllef,
+    eat_at_hp: u16,
+    tick_eat: bool,
+    max_time: u16,
+    seed: u64,
+) -> Vec<(Fight, Vec<(u16, Event)>)> {
+    let mut traces = Vec::new();
+
+    for (chunk, seed) in chunk_seeds(seed, fights).into_iter().enumerate() {
+        let rng = Rng::with_seed(seed);
+        let count = CHUNK_SIZE.min(fights - chunk as u32 * CHUNK_SIZE);
+        for _ in 0..count {
+            let mut events = Vec::new();
+            let fight = fight(
+                player,
+                hunllef,
+                eat_at_hp,
+                tick_eat,
+                max_time,
+                &rng,
+                |time, event| events.push((time, event)),
+            );
+            traces.push((fight, events));
+        }
+    }
+
+    traces
+}
+
+/// Searches for the fewest fish (up to the player's) giving at least the
+/// target success rate (0 to 1), returning the count and its result. Every
+/// count runs with the same seed, so success rises with fish and a binary
+/// search holds. None if even the player's fish fall short.
+pub fn min_fish(
+    trials: &Trials,
+    target: f64,
+    player: &Player,
+    hunllef: &Hunllef,
+    eat_at_hp: u16,
+    tick_eat: bool,
+    max_time: u16,
+) -> Option<(u8, SimulationResult)> {
+    let run = |fish: u8| {
+        let player = Player { fish, ..*player };
+        run_simulation(trials, &player, hunllef, eat_at_hp, tick_eat, max_time)
+    };
+
+    let mut best = (player.fish, run(player.fish));
+    if best.1.success_rate() < target {
+        return None;
+    }
+
+    //best always meets the target and everything below low falls short
+    let mut low = 0;
+    while low < best.0 {
+        let fish = low + (best.0 - low) / 2;
+        let result = run(fish);
+        if result.success_rate() >= target {
+            best = (fish, result);
+        } else {
+            low = fish + 1;
+        }
     }
-    success_rate
+    Some(best)
+}
+
+/// What `optimise_eating` ranks strategies by. Ties are broken by the
+/// faster mean kill time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Objective {
+    /// Highest success rate
+    SuccessRate,
+    /// Fewest fish eaten on average in successful fights, among strategies
+    /// with a success rate inside the best one's 95% interval
+    FishUsed,
+}
+
+/// When and how the player heals
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct EatStrategy {
+    pub eat_at_hp: u16,
+    pub tick_eat: bool,
+    pub redemption: u8,
+}
+
+/// Runs every strategy with the same seed and returns them with their
+/// results, best first
+pub fn optimise_eating(
+    trials: &Trials,
+    objective: Objective,
+    strategies: &[EatStrategy],
+    player: &Player,
+    hunllef: &Hunllef,
+    max_time: u16,
+) -> Vec<(EatStrategy, SimulationResult)> {
+    let mut ranked: Vec<_> = strategies
+        .iter()
+        .map(|strategy| {
+            let player = Player {
+                redemption: strategy.redemption,
+                ..*player
+            };
+            let result = run_simulation(
+                trials,
+                &player,
+                hunllef,
+                strategy.eat_at_hp,
+                strategy.tick_eat,
+                max_time,
+            );
+            (*strategy, result)
+        })
+        .collect();
+
+    rank(&mut ranked, objective);
+    ranked
+}
+
+//Sorts results best first by the objective, then by mean kill time
+fn rank<T>(ranked: &mut [(T, SimulationResult)], objective: Objective) {
+    //no successes means no kill time or fish used, which rank last
+    let last = |mean: f64| if mean.is_nan() { f64::INFINITY } else { mean };
+    //fights that succeed by luck eat less, so rarely successful strategies
+    //would look cheapest without a floor on the success rate
+    let floor = ranked
+        .iter()
+        .max_by_key(|(_, result)| result.success)
+        .map_or(0.0, |(_, result)| result.success_interval().0);
+    let short = |result: &SimulationResult| result.success_rate() < floor;
+    ranked.sort_by(|(_, a), (_, b)| {
+        let order = match objective {
+            Objective::SuccessRate => b.success.cmp(&a.success),
+            Objective::FishUsed => short(a)
+                .cmp(&short(b))
+                .then(last(a.mean_success_fish()).total_cmp(&last(b.mean_success_fish()))),
+        };
+        order.then(last(a.mean_time()).total_cmp(&last(b.mean_time())))
+    });
+}
+
+/// A weapon at a tier with the prayer used alongside it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Loadout {
+    pub weapon: Weapon,
+    pub tier: u8,
+    pub prayer: Prayer,
+}
+
+impl Loadout {
+    /// Every tier and matching prayer of a weapon
+    pub fn all(weapon: Weapon) -> Vec<Loadout> {
+        let prayers: &[Prayer] = match weapon {
+            Weapon::Bow => &[Prayer::Rigour, Prayer::EagleEye],
+            Weapon::Staff => &[Prayer::Augury, Prayer::MysticMight],
+            Weapon::Halberd => &[Prayer::Piety],
+        };
+        (1..=3)
+            .flat_map(|tier| {
+                prayers.iter().map(move |&prayer| Loadout {
+                    weapon,
+                    tier,
+                    prayer,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Every pair of loadouts with two different weapons. Setup order only
+/// matters to uneven rotations, so each pair comes once unless `ordered`.
+pub fn loadout_pairs(ordered: bool) -> Vec<(Loadout, Loadout)> {
+    let weapons = [Weapon::Bow, Weapon::Staff, Weapon::Halberd];
+    let mut pairs = Vec::new();
+    for (i, &first) in weapons.iter().enumerate() {
+        for (j, &second) in weapons.iter().enumerate() {
+            if i == j || (i > j && !ordered) {
+                continue;
+            }
+            for setup1 in Loadout::all(first) {
+                for setup2 in Loadout::all(second) {
+                    pairs.push((setup1, setup2));
+                }
+            }
+        }
+    }
+    pairs
+}
+
+/// Runs the player with every pair of loadouts (in the given armour tier) on
+/// the same seed, so they face the same random numbers, and returns them
+/// with their results, best first
+#[allow(clippy::too_many_arguments)]
+pub fn compare_setups(
+    trials: &Trials,
+    objective: Objective,
+    pairs: &[(Loadout, Loadout)],
+    tables: &StatTables,
+    armour_tier: u8,
+    player: &Player,
+    hunllef: &Hunllef,
+    eat_at_hp: u16,
+    tick_eat: bool,
+    max_time: u16,
+) -> Vec<((Loadout, Loadout), SimulationResult)> {
+    let levels = player.levels;
+    let mut ranked: Vec<_> = pairs
+        .iter()
+        .map(|&(loadout1, loadout2)| {
+            let setup = |l: Loadout| {
+                Setup::from_tables(tables, l.weapon, l.tier, l.prayer, levels, armour_tier)
+            };
+            let (setup1, setup2) = (setup(loadout1), setup(loadout2));
+            let player = Player {
+                setup1: &setup1,
+                setup2: &setup2,
+                current: &setup1,
+                ..*player
+            };
+            let result = run_simulation(trials, &player, hunllef, eat_at_hp, tick_eat, max_time);
+            ((loadout1, loadout2), result)
+        })
+        .collect();
+
+    rank(&mut ranked, objective);
+    ranked
 }
 
 #[cfg(test)]
 mod tests {
     use crate::*;
 
+    fn trials(count: u32, seed: u64) -> Trials {
+        Trials {
+            count,
+            seed,
+            threads: 2,
+            precision: None,
+        }
+    }
+
     const LVLS: Levels = Levels {
         attack: 99,
         strength: 99,
@@ -596,11 +1850,689 @@ mod tests {
         assert_eq!(setup.mdr, 27936);
     }
 
+    #[test]
+    fn seeded_runs_repeat() {
+        let bow = Setup::new(Weapon::Bow, 3, Prayer::Rigour, &LVLS, 1);
+        let staff = Setup::new(Weapon::Staff, 3, Prayer::Augury, &LVLS, 1);
+        let player = Player::new(&bow, &staff, &LVLS, 12, 0, 0);
+        let hunllef = Hunllef::new(1);
+
+        let first = run_simulation(&trials(2500, 42), &player, &hunllef, 50, false, 6000);
+        let second = run_simulation(&trials(2500, 42), &player, &hunllef, 50, false, 6000);
+        assert_eq!(first, second);
+
+        //splitting the same trials across more threads changes nothing
+        let trials = Trials {
+            threads: 5,
+            ..trials(2500, 42)
+        };
+        let third = run_simulation(&trials, &player, &hunllef, 50, false, 6000);
+        assert_eq!(first, third);
+    }
+
+    #[test]
+    fn streaming_stats() {
+        let mut stats = Stats::default();
+        assert_eq!((stats.min(), stats.max()), (None, None));
+        for value in [3, 9, 6] {
+            stats.record(value);
+        }
+        let mut other = Stats::default();
+        other.record(2);
+        stats.merge(&other);
+        assert_eq!((stats.count(), stats.sum()), (4, 20));
+        assert_eq!((stats.min(), stats.max()), (Some(2), Some(9)));
+        assert_eq!(stats.mean(), 5.0);
+        assert_eq!(stats.variance(), 10.0);
+        assert!((stats.std_error() - 2.5_f64.sqrt()).abs() < 1e-12);
+        assert_eq!(stats.histogram().len(), 4);
+
+        let bow = Setup::new(Weapon::Bow, 3, Prayer::Rigour, &LVLS, 1);
+        let staff = Setup::new(Weapon::Staff, 3, Prayer::Augury, &LVLS, 1);
+        let player = Player::new(&bow, &staff, &LVLS, 12, 0, 0);
+        let results = run_simulation(&trials(1500, 4), &player, &Hunllef::new(1), 50, false, 6000);
+        assert_eq!(results.trials, 1500);
+        assert_eq!(results.fish_eaten.count(), 1500);
+        assert_eq!(results.time.count(), results.success as u64);
+    }
+
+    #[test]
+    fn simulation_result() {
+        let bow = Setup::new(Weapon::Bow, 3, Prayer::Rigour, &LVLS, 1);
+        let staff = Setup::new(Weapon::Staff, 3, Prayer::Augury, &LVLS, 1);
+        let player = Player::new(&bow, &staff, &LVLS, 3, 0, 0);
+        let hunllef = Hunllef::new(1);
+
+        let mut first = run_simulation(&trials(500, 1), &player, &hunllef, 50, false, 6000);
+        let second = run_simulation(&trials(700, 2), &player, &hunllef, 50, false, 6000);
+        assert_eq!(first.success + first.failures.total(), 500);
+        assert!(first.failures.out_of_fish.count() > 0);
+        assert_eq!(first.failures.out_of_prayer.count(), 0);
+        assert_eq!(first.failures.max_time.count(), 0);
+
+        let success = first.success + second.success;
+        first.merge(&second);
+        assert_eq!((first.trials, first.success), (1200, success));
+        assert_eq!(first.success_rate(), success as f64 / 1200.0);
+        assert!(first.time_percentile(10.0) <= first.median_time());
+        assert!(first.median_time() <= first.time_percentile(90.0));
+        assert!(first.median_fish() <= 3);
+
+        let close = |(low, high): (f64, f64), expected: (f64, f64)| {
+            (low - expected.0).abs() < 1e-4 && (high - expected.1).abs() < 1e-4
+        };
+        let half = SimulationResult {
+            trials: 100,
+            success: 50,
+            ..Default::default()
+        };
+        assert!(close(half.success_interval(), (0.4038, 0.5962)));
+        let none = SimulationResult { success: 0, ..half };
+        assert!(close(none.success_interval(), (0.0, 0.0370)));
+        let (low, high) = first.success_interval();
+        assert!(low < first.success_rate() && first.success_rate() < high);
+
+        let with_fish = |fish| {
+            let player = Player { fish, ..player };
+            run_simulation(&trials(200, 3), &player, &hunllef, 50, false, 6000)
+        };
+        let (starved, fed) = (with_fish(0), with_fish(3));
+        assert_eq!((starved.trials, fed.trials), (200, 200));
+        assert!(starved.success < fed.success);
+    }
+
+    #[test]
+    fn failure_causes() {
+        let fight = Fight {
+            time: 300,
+            fish_eaten: 4,
+            fish_left: 0,
+            off_prayer_hits: 0,
+            doses_drunk: 0,
+            prayer: Some(50),
+            player_hp: 0,
+            hunllef_hp: 120,
+        };
+        assert_eq!(fight.failure(), Some(Failure::OutOfFish));
+        let with_fish = Fight {
+            fish_left: 2,
+            ..fight
+        };
+        assert_eq!(with_fish.failure(), Some(Failure::DiedWithFish));
+        let no_prayer = Fight {
+            player_hp: 40,
+            prayer: Some(0),
+            ..fight
+        };
+        assert_eq!(no_prayer.failure(), Some(Failure::OutOfPrayer));
+        let timed_out = Fight {
+            player_hp: 40,
+            ..fight
+        };
+        assert_eq!(timed_out.failure(), Some(Failure::MaxTime));
+        let killed = Fight {
+            player_hp: 40,
+            hunllef_hp: 0,
+            ..fight
+        };
+        assert_eq!(killed.failure(), None);
+
+        //never eating means dying with every fish left
+        let bow = Setup::new(Weapon::Bow, 1, Prayer::Rigour, &LVLS, 1);
+        let player = Player::new(&bow, &bow, &LVLS, 5, 0, 0);
+        let hunllef = Hunllef::new(1);
+        let result = run_simulation(&trials(200, 6), &player, &hunllef, 0, false, 6000);
+        let died = result.failures.cause(Failure::DiedWithFish);
+        assert_eq!(died.count(), result.failures.total() as u64);
+        assert!(died.min() > Some(0));
+
+        //off-prayer hits can take the player from above the threshold to 0,
+        //and a dead player doesn't get to eat
+        let player = Player::new(&bow, &bow, &LVLS, 20, 0, 0).with_off_prayer_rate(0.3);
+        let result = run_simulation(&trials(500, 6), &player, &hunllef, 50, false, 6000);
+        assert!(result.failures.cause(Failure::DiedWithFish).count() > 0);
+    }
+
+    #[test]
+    fn run_to_precision() {
+        let bow = Setup::new(Weapon::Bow, 3, Prayer::Rigour, &LVLS, 1);
+        let staff = Setup::new(Weapon::Staff, 3, Prayer::Augury, &LVLS, 1);
+        let hunllef = Hunllef::new(1);
+        let precision = Precision {
+            target: PrecisionTarget::SuccessRate,
+            width: 0.05,
+            max_trials: 20_000,
+        };
+        let trials = Trials {
+            precision: Some(precision),
+            ..trials(500, 8)
+        };
+
+        //hopeless and near-certain setups converge in a batch or two
+        let player = Player::new(&bow, &staff, &LVLS, 0, 0, 0);
+        let result = run_simulation(&trials, &player, &hunllef, 50, false, 6000);
+        assert!(result.trials <= 1000);
+        let (low, high) = result.success_interval();
+        assert!(high - low <= 0.05);
+
+        //a coin flip needs more, and repeats exactly
+        let player = Player::new(&bow, &staff, &LVLS, 5, 0, 0);
+        let result = run_simulation(&trials, &player, &hunllef, 50, false, 6000);
+        assert!(result.trials > 1000 && result.trials.is_multiple_of(500));
+        assert_eq!(
+            result,
+            run_simulation(&trials, &player, &hunllef, 50, false, 6000)
+        );
+
+        //mean time can't narrow with no successes
+        let player = Player::new(&bow, &staff, &LVLS, 0, 0, 0);
+        let trials = Trials {
+            precision: Some(Precision {
+                target: PrecisionTarget::MeanTime,
+                width: 1.0,
+                max_trials: 3000,
+            }),
+            ..trials
+        };
+        let result = run_simulation(&trials, &player, &hunllef, 50, false, 6000);
+        assert_eq!(result.trials, 3000);
+    }
+
+    #[test]
+    fn min_fish_search() {
+        let bow = Setup::new(Weapon::Bow, 3, Prayer::Rigour, &LVLS, 1);
+        let staff = Setup::new(Weapon::Staff, 3, Prayer::Augury, &LVLS, 1);
+        let player = Player::new(&bow, &staff, &LVLS, 20, 0, 0);
+        let hunllef = Hunllef::new(1);
+        let trials = trials(1000, 12);
+
+        let (fish, result) = min_fish(&trials, 0.9, &player, &hunllef, 50, false, 6000).unwrap();
+        assert!(result.success_rate() >= 0.9);
+        let with_fish = |fish| {
+            let player = Player { fish, ..player };
+            run_simulation(&trials, &player, &hunllef, 50, false, 6000)
+        };
+        assert_eq!(with_fish(fish), result);
+        //no fish at all can't reach 90%, so there's always a count below
+        assert!(fish > 0);
+        assert!(with_fish(fish - 1).success_rate() < 0.9);
+
+        let player = Player { fish: 2, ..player };
+        assert!(min_fish(&trials, 0.9, &player, &hunllef, 50, false, 6000).is_none());
+    }
+
+    #[test]
+    fn eat_threshold_optimiser() {
+        let bow = Setup::new(Weapon::Bow, 3, Prayer::Rigour, &LVLS, 1);
+        let staff = Setup::new(Weapon::Staff, 3, Prayer::Augury, &LVLS, 1);
+        let player = Player::new(&bow, &staff, &LVLS, 6, 0, 0);
+        let hunllef = Hunllef::new(1);
+        let strategies: Vec<_> = [5, 30, 50, 79]
+            .into_iter()
+            .map(|eat_at_hp| EatStrategy {
+                eat_at_hp,
+                tick_eat: false,
+                redemption: 0,
+            })
+            .collect();
+
+        let trials = trials(1000, 13);
+        let ranked = optimise_eating(
+            &trials,
+            Objective::SuccessRate,
+            &strategies,
+            &player,
+            &hunllef,
+            6000,
+        );
+        assert_eq!(ranked.len(), 4);
+        assert!(ranked.windows(2).all(|w| w[0].1.success >= w[1].1.success));
+
+        let ranked = optimise_eating(
+            &trials,
+            Objective::FishUsed,
+            &strategies,
+            &player,
+            &hunllef,
+            6000,
+        );
+        //strategies short of the best success rate rank after the rest, so
+        //eating too late can't win on the fish its lucky survivors ate
+        let best = ranked.iter().map(|(_, r)| r.success).max().unwrap();
+        let floor = ranked
+            .iter()
+            .find(|(_, r)| r.success == best)
+            .map(|(_, r)| r.success_interval().0)
+            .unwrap();
+        let reaching = ranked
+            .iter()
+            .take_while(|(_, r)| r.success_rate() >= floor)
+            .count();
+        assert!(reaching > 0);
+        assert!(ranked[reaching..]
+            .iter()
+            .all(|(_, r)| r.success_rate() < floor));
+        assert!(ranked[..reaching]
+            .windows(2)
+            .all(|w| w[0].1.mean_success_fish() <= w[1].1.mean_success_fish()));
+        assert_ne!(ranked[0].0.eat_at_hp, 5);
+    }
+
+    #[test]
+    fn setup_comparison() {
+        assert_eq!(loadout_pairs(false).len(), 72);
+        assert_eq!(loadout_pairs(true).len(), 144);
+
+        let bow = Setup::new(Weapon::Bow, 3, Prayer::Rigour, &LVLS, 1);
+        let player = Player::new(&bow, &bow, &LVLS, 12, 0, 0);
+        let hunllef = Hunllef::new(1);
+        let t1 = Loadout {
+            weapon: Weapon::Bow,
+            tier: 1,
+            prayer: Prayer::EagleEye,
+        };
+        let t3 = Loadout {
+            weapon: Weapon::Staff,
+            tier: 3,
+            prayer: Prayer::Augury,
+        };
+        let halberd = Loadout {
+            weapon: Weapon::Halberd,
+            tier: 3,
+            prayer: Prayer::Piety,
+        };
+        let pairs = [(t1, halberd), (t3, halberd)];
+        let ranked = compare_setups(
+            &trials(1000, 14),
+            Objective::SuccessRate,
+            &pairs,
+            StatTables::bundled(),
+            1,
+            &player,
+            &hunllef,
+            50,
+            false,
+            6000,
+        );
+        assert_eq!(ranked[0].0, (t3, halberd));
+        assert!(ranked[0].1.success > ranked[1].1.success);
+
+        //the same as running the setups directly
+        let staff = Setup::new(Weapon::Staff, 3, Prayer::Augury, &LVLS, 1);
+        let halberd = Setup::new(Weapon::Halberd, 3, Prayer::Piety, &LVLS, 1);
+        let player = Player::new(&staff, &halberd, &LVLS, 12, 0, 0);
+        let result = run_simulation(&trials(1000, 14), &player, &hunllef, 50, false, 6000);
+        assert_eq!(ranked[0].1, result);
+    }
+
+    #[test]
+    fn trace_matches_simulation() {
+        let bow = Setup::new(Weapon::Bow, 3, Prayer::Rigour, &LVLS, 1);
+        let staff = Setup::new(Weapon::Staff, 3, Prayer::Augury, &LVLS, 1);
+        let player = Player::new(&bow, &staff, &LVLS, 2, 0, 0);
+        let hunllef = Hunllef::new(1);
+
+        let success = run_simulation(&trials(200, 7), &player, &hunllef, 50, false, 6000).success;
+        let fights = trace_fights(200, &player, &hunllef, 50, false, 6000, 7);
+        assert_eq!(
+            fights.iter().filter(|(f, _)| f.success()).count(),
+            success as usize
+        );
+
+        //the fight ends with the death, so nothing is logged after it
+        for (fight, events) in fights {
+            let death = if fight.success() {
+                Event::HunllefDied
+            } else {
+                Event::PlayerDied
+            };
+            assert_eq!(events.last(), Some(&(fight.time - 1, death)));
+        }
+    }
+
+    #[test]
+    fn six_six_rotation() {
+        let bow = Setup::new(Weapon::Bow, 3, Prayer::Rigour, &LVLS, 1);
+        let staff = Setup::new(Weapon::Staff, 3, Prayer::Augury, &LVLS, 1);
+        let player = Player::new(&bow, &staff, &LVLS, 12, 0, 0);
+        let hunllef = Hunllef::new(1);
+
+        for (_, events) in trace_fights(20, &player, &hunllef, 50, false, 6000, 3) {
+            let switches = events
+                .iter()
+                .filter(|(_, e)| matches!(e, Event::SetupSwitch { .. }))
+                .count();
+            let attacks: Vec<_> = events
+                .iter()
+                .filter_map(|(_, e)| match e {
+                    Event::PlayerAttack { weapon, .. } => Some(*weapon),
+                    _ => None,
+                })
+                .collect();
+            assert!(attacks.contains(&Weapon::Bow) && attacks.contains(&Weapon::Staff));
+
+            //a streak is cut short only at the start, where it depends on the
+            //prayer hunllef starts on, and at the end of the fight
+            let streaks: Vec<_> = attacks.chunk_by(|a, b| a == b).map(<[_]>::len).collect();
+            assert_eq!(switches, streaks.len() - 1);
+            if let [_, middle @ .., _] = &streaks[..] {
+                assert!(middle.iter().all(|&streak| streak == 6));
+            }
+        }
+    }
+
+    #[test]
+    fn five_one_rotation() {
+        let bow = Setup::new(Weapon::Bow, 3, Prayer::Rigour, &LVLS, 1);
+        let halberd = Setup::new(Weapon::Halberd, 1, Prayer::Piety, &LVLS, 1);
+        let player = Player::new(&bow, &halberd, &LVLS, 12, 0, 0).with_rotation(Rotation::FiveOne);
+        let hunllef = Hunllef::new(1);
+
+        for (_, events) in trace_fights(20, &player, &hunllef, 50, false, 6000, 3) {
+            let mut attacks = events.iter().filter_map(|(_, e)| match e {
+                Event::PlayerAttack { weapon, .. } => Some(*weapon),
+                _ => None,
+            });
+            let mut streak = (attacks.next().unwrap(), 1);
+            //the opening streak depends on the prayer hunllef starts on, and
+            //the last can be cut short, but every other streak is exact
+            let mut opening = true;
+            for weapon in attacks {
+                if weapon == streak.0 {
+                    streak.1 += 1;
+                } else {
+                    let expected = if streak.0 == Weapon::Bow { 5 } else { 1 };
+                    assert!(opening || streak.1 == expected);
+                    opening = false;
+                    streak = (weapon, 1);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn misclicks_attack_into_prayer() {
+        let bow = Setup::new(Weapon::Bow, 3, Prayer::Rigour, &LVLS, 1);
+        let staff = Setup::new(Weapon::Staff, 3, Prayer::Augury, &LVLS, 1);
+        let hunllef = Hunllef::new(1);
+
+        let into_prayer = |player: &Player| {
+            trace_fights(50, player, &hunllef, 50, false, 6000, 5)
+                .iter()
+                .flat_map(|(_, events)| events)
+                .filter(|(_, e)| {
+                    matches!(
+                        e,
+                        Event::PlayerAttack {
+                            into_prayer: true,
+                            ..
+                        }
+                    )
+                })
+                .count()
+        };
+
+        let player = Player::new(&bow, &staff, &LVLS, 12, 0, 0);
+        assert_eq!(into_prayer(&player), 0);
+        let player = player.with_misclick_rate(0.5);
+        assert!(into_prayer(&player) > 0);
+    }
+
+    #[test]
+    fn unwinnable_fights_stop_at_cap() {
+        let bow = Setup::new(Weapon::Bow, 3, Prayer::Rigour, &LVLS, 1);
+        let staff = Setup::new(Weapon::Staff, 3, Prayer::Augury, &LVLS, 1);
+        let hunllef = Hunllef::new(0);
+
+        //always switching late means always attacking into Hunllef's prayer
+        let player = Player::new(&bow, &staff, &LVLS, 12, 0, 0).with_misclick_rate(1.0);
+        for (fight, _) in trace_fights(5, &player, &hunllef, 50, false, u16::MAX, 5) {
+            assert_eq!(fight.time, TICK_CAP + 1);
+            assert_eq!(fight.failure(), Some(Failure::MaxTime));
+        }
+    }
+
+    #[test]
+    fn off_prayer_hits() {
+        let bow = Setup::new(Weapon::Bow, 3, Prayer::Rigour, &LVLS, 1);
+        let staff = Setup::new(Weapon::Staff, 3, Prayer::Augury, &LVLS, 1);
+        let hunllef = Hunllef::new(1);
+
+        let player = Player::new(&bow, &staff, &LVLS, 12, 0, 0);
+        let results = run_simulation(&trials(100, 9), &player, &hunllef, 50, false, 6000);
+        assert_eq!(results.off_prayer_hits.max(), Some(0));
+
+        let player = player.with_off_prayer_rate(0.1);
+        let results = run_simulation(&trials(100, 9), &player, &hunllef, 50, false, 6000);
+        assert!(results.off_prayer_hits.sum() > 0);
+    }
+
+    #[test]
+    fn stomps_and_tornadoes() {
+        let bow = Setup::new(Weapon::Bow, 3, Prayer::Rigour, &LVLS, 1);
+        let staff = Setup::new(Weapon::Staff, 3, Prayer::Augury, &LVLS, 1);
+        let hunllef = Hunllef::new(1).with_tornado_damage(20, 20);
+        let player = Player::new(&bow, &staff, &LVLS, 12, 0, 0)
+            .with_stomp_rate(0.1)
+            .with_tornado_hit_rate(1.0);
+
+        let events: Vec<Event> = trace_fights(10, &player, &hunllef, 50, false, 6000, 11)
+            .into_iter()
+            .flat_map(|(_, events)| events.into_iter().map(|(_, e)| e))
+            .collect();
+        let tornadoes = events.iter().filter(|e| **e == Event::Tornado).count();
+        let clips = events
+            .iter()
+            .filter(|e| matches!(e, Event::TornadoHit { damage: 20, .. }))
+            .count();
+        assert_eq!(tornadoes, clips);
+        assert!(events.iter().any(|e| matches!(e, Event::Stomp { .. })));
+    }
+
+    #[test]
+    fn floor_corrupts_faster_at_low_hp() {
+        let mut hunllef = Hunllef::new(1);
+        let count = |hunllef: &mut Hunllef| (0..600).filter(|_| hunllef.corrupt_floor()).count();
+        let high = count(&mut hunllef);
+        hunllef.hp = 500;
+        let mid = count(&mut hunllef);
+        hunllef.hp = 100;
+        let low = count(&mut hunllef);
+        assert!(high < mid && mid < low);
+    }
+
+    #[test]
+    fn hits_land_after_delay() {
+        let bow = Setup::new(Weapon::Bow, 3, Prayer::Rigour, &LVLS, 1);
+        let staff = Setup::new(Weapon::Staff, 3, Prayer::Augury, &LVLS, 1);
+        let halberd = Setup::new(Weapon::Halberd, 3, Prayer::Piety, &LVLS, 1);
+        assert_eq!(
+            (bow.hit_delay, staff.hit_delay, halberd.hit_delay),
+            (2, 3, 1)
+        );
+
+        let player = Player::new(&bow, &staff, &LVLS, 12, 0, 0);
+        let hunllef = Hunllef::new(1);
+        let (_, events) = &trace_fights(1, &player, &hunllef, 50, false, 6000, 1)[0];
+        let first_hit = events.iter().find_map(|(time, e)| match e {
+            Event::PlayerAttack { weapon, .. } => Some((*time, *weapon)),
+            _ => None,
+        });
+        assert!(matches!(
+            first_hit,
+            Some((2, Weapon::Bow)) | Some((3, Weapon::Staff))
+        ));
+    }
+
+    #[test]
+    fn regeneration() {
+        let bow = Setup::new(Weapon::Bow, 3, Prayer::Rigour, &LVLS, 1);
+        let staff = Setup::new(Weapon::Staff, 3, Prayer::Augury, &LVLS, 1);
+        let mut player = Player::new(&bow, &staff, &LVLS, 12, 0, 0);
+        player.hp = 50;
+
+        let regen = |mut player: Player| (0..=300).filter(|_| player.regenerate()).count();
+        assert_eq!(regen(player), 3);
+        assert_eq!(regen(player.with_rapid_heal(true)), 6);
+
+        player.hp = 99;
+        assert_eq!(regen(player), 0);
+    }
+
+    #[test]
+    fn prayer_drain() {
+        let bow = Setup::new(Weapon::Bow, 3, Prayer::Rigour, &LVLS, 1);
+        let staff = Setup::new(Weapon::Staff, 3, Prayer::MysticMight, &LVLS, 3);
+        assert_eq!((bow.prayer_drain, bow.prayer_bonus), (36, 3));
+        assert_eq!((staff.prayer_drain, staff.prayer_bonus), (36, 9));
+
+        let hunllef = Hunllef::new(1);
+        let player = Player::new(&bow, &bow, &LVLS, 12, 0, 0);
+        let fights = trace_fights(
+            1,
+            &player.with_prayer_drain(0, 0),
+            &hunllef,
+            50,
+            false,
+            6000,
+            2,
+        );
+        let (fight, events) = &fights[0];
+        //99 points at 36/66 per tick
+        assert_eq!((fight.time, fight.prayer), (181, Some(0)));
+        assert_eq!(events.last(), Some(&(181, Event::OutOfPrayer)));
+
+        let player = Player::new(&bow, &staff, &LVLS, 12, 0, 0);
+        let player = player.with_prayer_drain(8, 20);
+        let results = run_simulation(&trials(100, 2), &player, &hunllef, 50, false, 6000);
+        assert!(results.success > 0);
+        assert!(results.doses_drunk.min() >= Some(1));
+        assert!(results.doses_drunk.max() <= Some(8));
+
+        //without drain, prayer points are never used up
+        let lvls = Levels { prayer: 0, ..LVLS };
+        let player = Player::new(&bow, &staff, &lvls, 12, 0, 0);
+        let results = run_simulation(&trials(100, 2), &player, &hunllef, 50, false, 6000);
+        assert!(results.success > 0);
+        assert_eq!(results.failures.out_of_prayer.count(), 0);
+    }
+
+    #[test]
+    fn stat_table_overrides() {
+        let mut tables = StatTables::bundled().clone();
+        tables.merge(
+            StatTables::from_toml(
+                r#"
+                [[weapon]]
+                weapon = "bow"
+                tier = 3
+                accuracy = 200
+                strength = 138
+
+                [[boss]]
+                variant = "corrupted"
+                hp = 900
+                level = 240
+                attack_bonus = 90
+                defence_bonus = 20
+                max_hit = [0, 14, 11, 9]
+                off_prayer_max_hit = 70
+                stomp_max_hit = 70
+                "#,
+            )
+            .unwrap(),
+        );
+        assert_eq!(tables.weapon.len(), StatTables::bundled().weapon.len());
+        assert_eq!(tables.weapon(Weapon::Bow, 3).accuracy, 200);
+
+        let setup = Setup::from_tables(&tables, Weapon::Bow, 3, Prayer::Rigour, &LVLS, 1);
+        assert_eq!(setup.acc_roll, 126 * (200 + 16 + 64));
+        assert_eq!(setup.max_hit, 41);
+        let staff = Setup::from_tables(&tables, Weapon::Staff, 3, Prayer::Augury, &LVLS, 1);
+        let bundled = Setup::new(Weapon::Staff, 3, Prayer::Augury, &LVLS, 1);
+        assert_eq!(staff.acc_roll, bundled.acc_roll);
+
+        let hunllef = Hunllef::from_tables(&tables, BossVariant::Corrupted, 2);
+        assert_eq!((hunllef.hp, hunllef.max_hit), (900, 11));
+        assert_eq!(hunllef.off_prayer_max_hit, 70);
+
+        assert!(StatTables::from_toml("[[weapon]]\nweapon = \"sword\"").is_err());
+        //typos are errors rather than silently leaving the bundled stats
+        assert!(StatTables::from_toml("[[weapons]]\naccuracy = 999").is_err());
+        let misspelt = "[[weapon]]\nweapon = \"bow\"\ntier = 3\naccuracy = 999\nstrenght = 1";
+        assert!(StatTables::from_toml(misspelt).is_err());
+    }
+
+    #[test]
+    fn oversized_stat_overrides() {
+        //rolls past u16::MAX, and enough hp to overflow the floor's phase maths
+        let mut tables = StatTables::bundled().clone();
+        tables.merge(
+            StatTables::from_toml(
+                r#"
+                [[weapon]]
+                weapon = "bow"
+                tier = 3
+                accuracy = 500
+                strength = 138
+
+                [[boss]]
+                variant = "corrupted"
+                hp = 30000
+                level = 240
+                attack_bonus = 200
+                defence_bonus = 200
+                max_hit = [0, 14, 11, 9]
+                off_prayer_max_hit = 70
+                stomp_max_hit = 70
+                "#,
+            )
+            .unwrap(),
+        );
+
+        let bow = Setup::from_tables(&tables, Weapon::Bow, 3, Prayer::Rigour, &LVLS, 1);
+        assert_eq!(bow.acc_roll, 126 * (500 + 16 + 64));
+        let staff = Setup::from_tables(&tables, Weapon::Staff, 3, Prayer::Augury, &LVLS, 1);
+        let hunllef = Hunllef::from_tables(&tables, BossVariant::Corrupted, 1);
+        assert_eq!(hunllef.acc_roll, 249 * 264);
+        assert_eq!(hunllef.defensive_roll, 249 * 264);
+
+        let player = Player::new(&bow, &staff, &LVLS, 12, 0, 0).with_floor_mistake_rate(0.1);
+        let fights = trace_fights(5, &player, &hunllef, 50, false, 6000, 5);
+        assert!(fights
+            .iter()
+            .flat_map(|(_, events)| events)
+            .any(|(_, e)| matches!(e, Event::FloorCorrupted)));
+    }
+
     #[test]
     fn hunllef_stats() {
         let hunllef = Hunllef::new(1);
         assert_eq!(hunllef.max_hit, 13);
+        assert_eq!(hunllef.off_prayer_max_hit, 68);
         assert_eq!(hunllef.acc_roll, 38346);
         assert_eq!(hunllef.defensive_roll, 20916);
     }
+
+    #[test]
+    fn crystalline_hunllef() {
+        let hunllef = Hunllef::for_variant(BossVariant::Crystalline, 1);
+        assert_eq!((hunllef.hp, hunllef.max_hp), (600, 600));
+        assert_eq!(hunllef.max_hit, 8);
+        assert_eq!(hunllef.off_prayer_max_hit, 40);
+        //the tables assume the corrupted Hunllef's level and bonuses
+        let corrupted = Hunllef::new(1);
+        assert_eq!(hunllef.acc_roll, corrupted.acc_roll);
+        assert_eq!(hunllef.defensive_roll, corrupted.defensive_roll);
+
+        //fewer hp and smaller hits make for faster, safer kills
+        let bow = Setup::new(Weapon::Bow, 3, Prayer::Rigour, &LVLS, 1);
+        let staff = Setup::new(Weapon::Staff, 3, Prayer::Augury, &LVLS, 1);
+        let player = Player::new(&bow, &staff, &LVLS, 4, 0, 0);
+        let trials = trials(1000, 15);
+        let corrupted = run_simulation(&trials, &player, &corrupted, 50, false, 6000);
+        let crystalline = run_simulation(&trials, &player, &hunllef, 50, false, 6000);
+        assert!(crystalline.success > corrupted.success);
+        assert!(crystalline.mean_time() < corrupted.mean_time());
+    }
 }
@@ -1,15 +1,79 @@
-use clap::Parser;
-use hdrhistogram::Histogram;
-use hunllef::{Hunllef, Levels, Player, Prayer, Setup, Weapon};
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use hunllef::{
+    BossVariant, EatStrategy, Event, Failure, Fight, FloorTiles, Hunllef, Levels, Loadout,
+    Objective, Player, Prayer, Precision, PrecisionTarget, Rotation, Setup, SimulationResult,
+    StatTables, Trials, Weapon,
+};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use output::Output;
+use profiles::Profiles;
+
+mod output;
+mod profiles;
 
 #[derive(Parser, Debug)]
 #[command(name = "Hunllef")]
 #[command(version = "0.1")]
 #[command(about = "Simulates the Corrupted Hunllef fight", long_about = None)]
 struct Cli {
-    /// Number of simulations
-    #[arg(short, long, default_value_t = 100_000)]
-    trials: u32,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Runs trials and prints success rate, times and fish eaten
+    Simulate(SimulateArgs),
+
+    /// Runs trials for each value of an option (0 fish to --fish by default)
+    Sweep(SweepArgs),
+
+    /// Ranks eat thresholds, or finds the fewest fish for a success rate
+    Optimise(OptimiseArgs),
+
+    /// Prints a tick-by-tick log of fights instead of statistics
+    Trace(TraceArgs),
+
+    /// Ranks every pair of weapons, tiers and prayers
+    Compare(CompareArgs),
+
+    /// Teammates' levels and saved scenarios
+    Profile {
+        #[command(subcommand)]
+        action: ProfileAction,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ProfileAction {
+    /// Runs every scenario of a profile and prints a comparison table
+    Run(ProfileRunArgs),
+}
+
+/// The fight: player, setups, Hunllef and mistakes, shared by every subcommand
+#[derive(Args, Debug, Clone, Serialize, Deserialize)]
+struct Scenario {
+    /// TOML scenario file of options (named as in --dump-config), overridden
+    /// by any given on the command line
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    config: Option<PathBuf>,
+
+    /// Prints the options as run in the --config format instead of running
+    #[arg(long, default_value_t = false)]
+    #[serde(default, skip_serializing_if = "is_false")]
+    dump_config: bool,
 
     /// Number to eat (heal 20 hp)
     #[arg(short, long, default_value_t = 12)]
@@ -19,6 +83,14 @@ struct Cli {
     #[arg(short, long, default_value_t = 1)]
     armour: u8,
 
+    /// TOML file of stats overriding the bundled ones (see src/tables.toml)
+    #[arg(long)]
+    stats: Option<PathBuf>,
+
+    /// Hunllef to fight: corrupted or the regular gauntlet's crystalline
+    #[arg(long, value_enum, default_value_t = BossVariant::Corrupted)]
+    boss: BossVariant,
+
     ///1st setup weapon
     #[arg(long, value_enum, default_value_t = Weapon::Bow)]
     setup1: Weapon,
@@ -43,6 +115,54 @@ struct Cli {
     #[arg(long, value_enum, default_value_t = Prayer::Augury)]
     setup2_prayer: Prayer,
 
+    /// Attacks with each setup before switching
+    #[arg(long, value_enum, default_value_t = Rotation::SixSix)]
+    rotation: Rotation,
+
+    /// Chance of switching setup one attack late (into Hunllef's prayer)
+    #[arg(long, default_value_t = 0.0, value_parser = parse_rate)]
+    misclick_rate: f64,
+
+    /// Chance of being on the wrong protection prayer for each Hunllef attack
+    #[arg(long, default_value_t = 0.0, value_parser = parse_rate)]
+    off_prayer_rate: f64,
+
+    /// Chance of being under Hunllef (and stomped) for each Hunllef attack
+    #[arg(long, default_value_t = 0.0, value_parser = parse_rate)]
+    stomp_rate: f64,
+
+    /// Chance of being clipped by each set of tornadoes
+    #[arg(long, default_value_t = 0.0, value_parser = parse_rate)]
+    tornado_hit_rate: f64,
+
+    /// Minimum damage from a tornado
+    #[arg(long, default_value_t = 15)]
+    tornado_min_damage: u16,
+
+    /// Maximum damage from a tornado
+    #[arg(long, default_value_t = 30)]
+    tornado_max_damage: u16,
+
+    /// Chance of standing on a corrupted floor tile for each tick the floor is corrupted
+    #[arg(long, default_value_t = 0.0, value_parser = parse_rate)]
+    floor_mistake_rate: f64,
+
+    /// Damage per tick stood on a corrupted floor tile
+    #[arg(long, default_value_t = 15)]
+    floor_damage: u16,
+
+    /// Doubles natural hp regeneration (rapid heal style effect)
+    #[arg(long, default_value_t = false)]
+    rapid_heal: bool,
+
+    /// Egniol doses to bring; simulates prayer drain if set
+    #[arg(long)]
+    egniol_doses: Option<u8>,
+
+    /// Prayer points to drink an egniol dose at
+    #[arg(long, default_value_t = 20)]
+    drink_at_prayer: u16,
+
     /// Player Attack Level
     #[arg(long, default_value_t = 99)]
     attack: u8,
@@ -87,29 +207,272 @@ struct Cli {
     #[arg(long, default_value_t = 0)]
     lost_ticks: u8,
 
-    /// Max time for successful run (in ticks)
+    /// Max time for successful run (in ticks, at most 10000)
     #[arg(long, default_value_t = 6000)]
     max_time: u16,
 
+    /// Seed for the random number generator (random if not set)
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Format to print results in; json and csv include the parameters used
+    #[arg(long, value_enum, default_value_t = Output::Text)]
+    output: Output,
+}
+
+/// How many trials to run, for the subcommands reporting statistics
+#[derive(Args, Debug, Clone, Serialize, Deserialize)]
+struct Batches {
+    /// Number of simulations
+    #[arg(short, long, default_value_t = 100_000)]
+    trials: u32,
+
+    /// Runs batches of --trials until the 95% CI is no wider than this
+    /// (percentage points of success rate, or ticks of mean time)
+    #[arg(long)]
+    precision: Option<f64>,
+
+    /// Interval to narrow when running to a precision
+    #[arg(long, value_enum, default_value_t = PrecisionTarget::SuccessRate)]
+    precision_target: PrecisionTarget,
+
+    /// Most trials to run when running to a precision
+    #[arg(long, default_value_t = 10_000_000)]
+    max_trials: u32,
+
+    /// Worker threads to run trials on (defaults to available cores)
+    #[arg(long)]
+    threads: Option<usize>,
+
     /// Histogram values for times/fish_eaten
     #[arg(long, default_value_t = false)]
     histogram: bool,
+}
+
+#[derive(Args, Debug, Clone, Serialize, Deserialize)]
+struct SimulateArgs {
+    #[command(flatten)]
+    #[serde(flatten)]
+    scenario: Scenario,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    batches: Batches,
+}
+
+/// Options `sweep` can step through
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum SweepOption {
+    Fish,
+    EatAtHp,
+    Armour,
+    Setup1Tier,
+    Setup2Tier,
+    EgniolDoses,
+}
+
+impl SweepOption {
+    //name of the option in parameters and --config files
+    fn name(self) -> &'static str {
+        match self {
+            SweepOption::Fish => "fish",
+            SweepOption::EatAtHp => "eat_at_hp",
+            SweepOption::Armour => "armour",
+            SweepOption::Setup1Tier => "setup1_tier",
+            SweepOption::Setup2Tier => "setup2_tier",
+            SweepOption::EgniolDoses => "egniol_doses",
+        }
+    }
+
+    //values swept when --from and --to aren't set
+    fn range(self, scenario: &Scenario) -> (u8, u8) {
+        match self {
+            SweepOption::Fish => (0, scenario.fish),
+            SweepOption::EatAtHp => (0, scenario.hp),
+            SweepOption::Armour | SweepOption::Setup1Tier | SweepOption::Setup2Tier => (1, 3),
+            SweepOption::EgniolDoses => (0, scenario.egniol_doses.unwrap_or(0)),
+        }
+    }
+
+    //values the option can take at all, for the tiers the stat tables cover
+    fn limits(self) -> Option<RangeInclusive<u8>> {
+        match self {
+            SweepOption::Armour | SweepOption::Setup1Tier | SweepOption::Setup2Tier => Some(1..=3),
+            SweepOption::Fish | SweepOption::EatAtHp | SweepOption::EgniolDoses => None,
+        }
+    }
+
+    fn set(self, scenario: &mut Scenario, value: u8) {
+        match self {
+            SweepOption::Fish => scenario.fish = value,
+            SweepOption::EatAtHp => scenario.eat_at_hp = value.into(),
+            SweepOption::Armour => scenario.armour = value,
+            SweepOption::Setup1Tier => scenario.setup1_tier = value,
+            SweepOption::Setup2Tier => scenario.setup2_tier = value,
+            SweepOption::EgniolDoses => scenario.egniol_doses = Some(value),
+        }
+    }
+}
+
+#[derive(Args, Debug, Clone, Serialize, Deserialize)]
+struct SweepArgs {
+    #[command(flatten)]
+    #[serde(flatten)]
+    scenario: Scenario,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    batches: Batches,
+
+    /// Option to step through
+    #[arg(long, value_enum, default_value_t = SweepOption::Fish)]
+    over: SweepOption,
+
+    /// First value (defaults to the lowest the option takes)
+    #[arg(long)]
+    from: Option<u8>,
+
+    /// Last value (defaults to the option's value, or the highest tier)
+    #[arg(long)]
+    to: Option<u8>,
+
+    /// Step between values
+    #[arg(long, default_value_t = 1)]
+    step: u8,
+}
+
+#[derive(Args, Debug, Clone, Serialize, Deserialize)]
+struct OptimiseArgs {
+    #[command(flatten)]
+    #[serde(flatten)]
+    scenario: Scenario,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    batches: Batches,
+
+    /// Ranks eat-at-hp thresholds (every --eat-step hp up to the HP level) by
+    /// this objective
+    #[arg(long, value_enum, required_unless_present = "min_fish")]
+    objective: Option<Objective>,
 
-    /// Outputs success rate for each amount of food from 1 to set value
+    /// HP between the thresholds tried by --objective
+    #[arg(long, default_value_t = 5)]
+    eat_step: u16,
+
+    /// Also tries tick eating on and off and each redemption count up to
+    /// --redemption with --objective
+    #[cfg(feature = "advanced")]
     #[arg(long, default_value_t = false)]
-    data_mode: bool,
+    eat_combos: bool,
+
+    /// Finds the fewest fish (up to --fish) giving this success rate (%)
+    #[arg(long, conflicts_with = "objective")]
+    min_fish: Option<f64>,
+}
+
+#[derive(Args, Debug, Clone, Serialize, Deserialize)]
+struct TraceArgs {
+    #[command(flatten)]
+    #[serde(flatten)]
+    scenario: Scenario,
+
+    /// Number of fights to log
+    #[arg(long, default_value_t = 1)]
+    fights: u32,
+}
+
+#[derive(Args, Debug, Clone, Serialize, Deserialize)]
+struct CompareArgs {
+    #[command(flatten)]
+    #[serde(flatten)]
+    scenario: Scenario,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    batches: Batches,
+
+    /// Objective to rank the pairs by
+    #[arg(long, value_enum, default_value_t = Objective::SuccessRate)]
+    objective: Objective,
+
+    /// Restricts a weapon to one tier (e.g. bow:2)
+    #[arg(long, value_parser = parse_weapon_tier)]
+    only_tier: Vec<(Weapon, u8)>,
+
+    /// Leaves a prayer out
+    #[arg(long, value_enum)]
+    exclude_prayer: Vec<Prayer>,
+}
+
+#[derive(Args, Debug, Clone, Serialize, Deserialize)]
+struct ProfileRunArgs {
+    /// Name of the profile
+    name: String,
+
+    /// TOML file of profiles
+    #[arg(long, default_value = "profiles.toml")]
+    profiles: PathBuf,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    scenario: Scenario,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    batches: Batches,
+}
+
+/// Arguments of a subcommand, which --config and --dump-config apply to
+trait Invocation: Serialize + DeserializeOwned {
+    fn scenario(&mut self) -> &mut Scenario;
+
+    fn batches(&mut self) -> Option<&mut Batches>;
 }
-fn generate_histogram(times: &[u16], fish_eaten: &[u64]) {
+
+macro_rules! invocation {
+    ($args:ty) => {
+        impl Invocation for $args {
+            fn scenario(&mut self) -> &mut Scenario {
+                &mut self.scenario
+            }
+
+            fn batches(&mut self) -> Option<&mut Batches> {
+                Some(&mut self.batches)
+            }
+        }
+    };
+}
+
+invocation!(SimulateArgs);
+invocation!(SweepArgs);
+invocation!(OptimiseArgs);
+invocation!(CompareArgs);
+invocation!(ProfileRunArgs);
+
+impl Invocation for TraceArgs {
+    fn scenario(&mut self) -> &mut Scenario {
+        &mut self.scenario
+    }
+
+    fn batches(&mut self) -> Option<&mut Batches> {
+        None
+    }
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+fn generate_histogram(result: &SimulationResult) {
     fn tick_to_secs(ticks: u64) -> String {
         let min = ticks / 100;
         let sec = ticks * 3 / 5 % 60;
         format!("{}:{:#02}", min, sec)
     }
 
-    let mut hist = Histogram::<u64>::new(3).unwrap();
-    for num in times {
-        hist.record(*num as u64).unwrap();
-    }
+    let hist = result.time.histogram();
 
     println!("\nHistograms");
     println!("Time (m:ss) - {} samples", hist.len());
@@ -121,10 +484,7 @@ fn generate_histogram(times: &[u16], fish_eaten: &[u64]) {
     println!("97.5'th %: {}", tick_to_secs(hist.value_at_quantile(0.975)));
     println!("99.5'th %: {}", tick_to_secs(hist.value_at_quantile(0.995)));
 
-    let mut hist = Histogram::<u64>::new(3).unwrap();
-    for num in fish_eaten {
-        hist.record(*num).unwrap();
-    }
+    let hist = result.fish_eaten.histogram();
 
     println!("\nFish eaten - {} samples (includes failures)", hist.len());
     println!("  .5'th %: {}", hist.value_at_quantile(0.005));
@@ -136,77 +496,863 @@ fn generate_histogram(times: &[u16], fish_eaten: &[u64]) {
     println!("99.5'th %: {}", hist.value_at_quantile(0.995));
 }
 
-fn main() {
-    let args = Cli::parse();
+fn print_results(result: &SimulationResult, scenario: &Scenario, batches: &Batches) {
+    println!("seed: {}", scenario.seed.unwrap());
+    if batches.precision.is_some() {
+        println!("trials: {}", result.trials);
+    }
+    let (low, high) = result.success_interval();
+    println!(
+        "success rate: {:.2}% (95% CI {:.2}-{:.2}%)",
+        result.success_rate() * 100.0,
+        low * 100.0,
+        high * 100.0
+    );
+    println!(
+        "avg fish eaten: {:.1} (se {:.2})",
+        result.mean_fish(),
+        result.fish_std_error()
+    );
+    println!(
+        "avg time: {:.1} ticks (se {:.2})",
+        result.mean_time(),
+        result.time_std_error()
+    );
+    println!("median time: {} ticks", result.median_time());
+    for failure in [
+        Failure::OutOfFish,
+        Failure::DiedWithFish,
+        Failure::OutOfPrayer,
+        Failure::MaxTime,
+    ] {
+        let hunllef_hp = result.failures.cause(failure);
+        if hunllef_hp.count() > 0 {
+            println!(
+                "{failure}: {} ({:.2}%), avg hunllef hp left {:.1}",
+                hunllef_hp.count(),
+                hunllef_hp.count() as f64 * 100.0 / result.trials as f64,
+                hunllef_hp.mean()
+            );
+        }
+    }
+    if scenario.off_prayer_rate > 0.0 {
+        println!("avg off-prayer hits: {:.1}", result.off_prayer_hits.mean());
+    }
+    if scenario.egniol_doses.is_some() {
+        println!("avg egniol doses drunk: {:.1}", result.doses_drunk.mean());
+    }
+
+    if batches.histogram {
+        generate_histogram(result);
+    }
+}
+
+fn parse_weapon_tier(s: &str) -> Result<(Weapon, u8), String> {
+    let (weapon, tier) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected <WEAPON>:<TIER>, found '{s}'"))?;
+    let weapon = Weapon::from_str(weapon, true)?;
+    match tier.parse() {
+        Ok(tier @ 1..=3) => Ok((weapon, tier)),
+        _ => Err(format!("tier must be 1, 2 or 3, found '{tier}'")),
+    }
+}
+
+fn parse_rate(s: &str) -> Result<f64, String> {
+    let rate = s
+        .parse()
+        .map_err(|_| format!("expected a number, found '{s}'"))?;
+    check_rate(rate)
+}
+
+//Mistake rates are chances, whether given on the command line or in a file
+fn check_rate(rate: f64) -> Result<f64, String> {
+    if (0.0..=1.0).contains(&rate) {
+        Ok(rate)
+    } else {
+        Err(format!("must be between 0 and 1, found {rate}"))
+    }
+}
+
+fn describe_loadout(loadout: &Loadout) -> String {
+    format!(
+        "T{} {:?} ({:?})",
+        loadout.tier, loadout.weapon, loadout.prayer
+    )
+}
+
+//Eating strategies for `optimise --objective` to rank
+fn eat_strategies(args: &OptimiseArgs) -> Vec<EatStrategy> {
+    let scenario = &args.scenario;
+    #[cfg(feature = "advanced")]
+    let (tick_eat, redemption) = if args.eat_combos {
+        (vec![false, true], (0..=scenario.redemption).collect())
+    } else {
+        (vec![scenario.tick_eat], vec![scenario.redemption])
+    };
+    #[cfg(not(feature = "advanced"))]
+    let (tick_eat, redemption) = (vec![scenario.tick_eat], vec![scenario.redemption]);
+
+    let thresholds: Vec<u16> = (args.eat_step..=scenario.hp as u16)
+        .step_by(args.eat_step.max(1) as usize)
+        .collect();
+    let mut strategies = Vec::new();
+    for &tick_eat in &tick_eat {
+        //tick eating ignores the threshold, so it only needs trying once
+        let thresholds = if tick_eat { &[0][..] } else { &thresholds[..] };
+        for &eat_at_hp in thresholds {
+            for &redemption in &redemption {
+                strategies.push(EatStrategy {
+                    eat_at_hp,
+                    tick_eat,
+                    redemption,
+                });
+            }
+        }
+    }
+    strategies
+}
+
+fn describe_strategy(strategy: &EatStrategy) -> String {
+    let mut description = if strategy.tick_eat {
+        "tick eat".to_string()
+    } else {
+        format!("eat at {} hp", strategy.eat_at_hp)
+    };
+    if strategy.redemption > 0 {
+        description += &format!(", {} redemption", strategy.redemption);
+    }
+    description
+}
+
+fn print_log(fights: &[(Fight, Vec<(u16, Event)>)], seed: u64) {
+    for (n, (fight, events)) in fights.iter().enumerate() {
+        println!("Fight {} (seed {seed})", n + 1);
+        for (time, event) in events {
+            println!("  t={time:0>3} {event}");
+        }
+        if fight.success() {
+            println!("SUCCESS in {} ticks", fight.time);
+        } else {
+            println!(
+                "FAILURE ({}) after {} ticks with hunllef at {} hp",
+                fight.failure().unwrap(),
+                fight.time,
+                fight.hunllef_hp
+            );
+        }
+        println!("{} fish eaten\n", fight.fish_eaten);
+    }
+}
+
+//Reads and parses a file, exiting with an error if either fails
+fn load<T>(path: &Path, what: &str, parse: impl FnOnce(&str) -> Result<T, String>) -> T {
+    match fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|contents| parse(&contents))
+    {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("error: couldn't load {what} from {}: {e}", path.display());
+            process::exit(1);
+        }
+    }
+}
+
+//Option names of every subcommand
+fn option_names(command: &clap::Command) -> Vec<String> {
+    command
+        .get_arguments()
+        .map(|arg| arg.get_id().to_string())
+        .chain(command.get_subcommands().flat_map(option_names))
+        .collect()
+}
+
+//Takes options from a --config file or profile scenario unless they were
+//given on the command line. Options of other subcommands are skipped, so one
+//file can be shared between them.
+fn apply_config<T: Invocation>(
+    args: &T,
+    config: toml::Table,
+    matches: &ArgMatches,
+) -> Result<T, String> {
+    let known = option_names(&Cli::command());
+    let mut merged = serde_json::to_value(args).unwrap();
+    for (key, value) in config {
+        if key == "config" || key == "dump_config" {
+            return Err(format!("'{key}' can't be set from a file"));
+        }
+        if merged.get(&key).is_none() {
+            if known.contains(&key) {
+                continue;
+            }
+            return Err(format!("unknown option '{key}'"));
+        }
+        if matches.value_source(&key) != Some(ValueSource::CommandLine) {
+            merged[&key] = serde_json::to_value(value).map_err(|e| e.to_string())?;
+        }
+    }
+    serde_json::from_value(merged).map_err(|e| e.to_string())
+}
+
+//Applies --config, handles --dump-config, and fills in the seed and thread
+//count
+fn prepare<T: Invocation>(mut args: T, matches: &ArgMatches) -> T {
+    if let Some(path) = args.scenario().config.clone() {
+        let config = load(&path, "config", |toml| {
+            toml::from_str(toml).map_err(|e| e.to_string())
+        });
+        args = apply_config(&args, config, matches).unwrap_or_else(|e| {
+            eprintln!("error: invalid config {}: {e}", path.display());
+            process::exit(1);
+        });
+    }
+
+    //TOML integers are signed, so random seeds are kept to ones --dump-config can write
+    args.scenario()
+        .seed
+        .get_or_insert_with(|| fastrand::u64(..=i64::MAX as u64));
+    if args.scenario().dump_config {
+        args.scenario().config = None;
+        args.scenario().dump_config = false;
+        match toml::to_string(&args) {
+            Ok(config) => print!("{config}"),
+            Err(e) => {
+                eprintln!("error: couldn't write config: {e}");
+                process::exit(1);
+            }
+        }
+        process::exit(0);
+    }
+
+    //filled in after dumping, so a shared config doesn't pin this machine's
+    //core count unless it was asked for
+    if let Some(batches) = args.batches() {
+        batches
+            .threads
+            .get_or_insert_with(|| thread::available_parallelism().map_or(1, |n| n.get()));
+    }
+    args
+}
+
+//Checks the options clap can't check one at a time, and those that came
+//from a file rather than the command line
+fn validate(scenario: &Scenario) -> Result<(), String> {
+    if scenario.tornado_min_damage > scenario.tornado_max_damage {
+        return Err(format!(
+            "--tornado-min-damage ({}) is above --tornado-max-damage ({})",
+            scenario.tornado_min_damage, scenario.tornado_max_damage
+        ));
+    }
+    for (name, rate) in [
+        ("misclick-rate", scenario.misclick_rate),
+        ("off-prayer-rate", scenario.off_prayer_rate),
+        ("stomp-rate", scenario.stomp_rate),
+        ("tornado-hit-rate", scenario.tornado_hit_rate),
+        ("floor-mistake-rate", scenario.floor_mistake_rate),
+    ] {
+        check_rate(rate).map_err(|e| format!("invalid value for '--{name}': {e}"))?;
+    }
+    Ok(())
+}
+
+//Builds the stat tables, player and Hunllef a scenario describes
+fn with_fight<R>(scenario: &Scenario, f: impl FnOnce(&StatTables, &Player, &Hunllef) -> R) -> R {
+    if let Err(e) = validate(scenario) {
+        Cli::command().error(ErrorKind::ValueValidation, e).exit();
+    }
 
     let levels = Levels {
-        attack: args.attack,
-        strength: args.strength,
-        defence: args.defence,
-        ranged: args.ranged,
-        magic: args.magic,
-        prayer: args.prayer,
-        hp: args.hp,
+        attack: scenario.attack,
+        strength: scenario.strength,
+        defence: scenario.defence,
+        ranged: scenario.ranged,
+        magic: scenario.magic,
+        prayer: scenario.prayer,
+        hp: scenario.hp,
     };
 
-    let setup1 = Setup::new(
-        args.setup1,
-        args.setup1_tier,
-        args.setup1_prayer,
+    let mut tables = StatTables::bundled().clone();
+    if let Some(path) = &scenario.stats {
+        tables.merge(load(path, "stats", |toml| {
+            StatTables::from_toml(toml).map_err(|e| e.to_string())
+        }));
+    }
+    //the lookups panic on a missing row, which --stats can add tiers for
+    for (option, weapon, tier) in [
+        ("setup1-tier", scenario.setup1, scenario.setup1_tier),
+        ("setup2-tier", scenario.setup2, scenario.setup2_tier),
+    ] {
+        if !tables
+            .weapon
+            .iter()
+            .any(|w| w.weapon == weapon && w.tier == tier)
+        {
+            Cli::command()
+                .error(
+                    ErrorKind::ValueValidation,
+                    format!("invalid value '{tier}' for '--{option}': no stats for tier {tier} {weapon:?}"),
+                )
+                .exit();
+        }
+    }
+
+    let setup1 = Setup::from_tables(
+        &tables,
+        scenario.setup1,
+        scenario.setup1_tier,
+        scenario.setup1_prayer,
         &levels,
-        args.armour,
+        scenario.armour,
     );
-    let setup2 = Setup::new(
-        args.setup2,
-        args.setup2_tier,
-        args.setup2_prayer,
+    let setup2 = Setup::from_tables(
+        &tables,
+        scenario.setup2,
+        scenario.setup2_tier,
+        scenario.setup2_prayer,
         &levels,
-        args.armour,
+        scenario.armour,
     );
 
     let player = Player::new(
         &setup1,
         &setup2,
         &levels,
-        args.fish,
-        args.redemption,
-        args.lost_ticks,
-    );
+        scenario.fish,
+        scenario.redemption,
+        scenario.lost_ticks,
+    )
+    .with_rotation(scenario.rotation)
+    .with_misclick_rate(scenario.misclick_rate)
+    .with_off_prayer_rate(scenario.off_prayer_rate)
+    .with_stomp_rate(scenario.stomp_rate)
+    .with_tornado_hit_rate(scenario.tornado_hit_rate)
+    .with_floor_mistake_rate(scenario.floor_mistake_rate)
+    .with_rapid_heal(scenario.rapid_heal);
+    let player = match scenario.egniol_doses {
+        Some(doses) => player.with_prayer_drain(doses, scenario.drink_at_prayer),
+        None => player,
+    };
 
-    let hunllef = Hunllef::new(args.armour);
+    let hunllef = Hunllef::from_tables(&tables, scenario.boss, scenario.armour)
+        .with_tornado_damage(scenario.tornado_min_damage, scenario.tornado_max_damage)
+        .with_floor_tiles(FloorTiles {
+            damage: scenario.floor_damage,
+            ..FloorTiles::default()
+        });
 
-    if args.data_mode {
-        let success = hunllef::data_mode(
-            args.trials,
-            &player,
-            &hunllef,
-            args.eat_at_hp,
-            args.tick_eat,
-            args.max_time,
-        );
-        for rate in success{
-            let success_rate = (rate as f32 * 100.0) / (args.trials as f32);
-            print!("{success_rate:.2}, ");
+    f(&tables, &player, &hunllef)
+}
+
+//Trials to run; prepare fills in the seed and thread count
+fn trials(scenario: &Scenario, batches: &Batches) -> Trials {
+    let precision = batches.precision.map(|width| Precision {
+        target: batches.precision_target,
+        width: match batches.precision_target {
+            PrecisionTarget::SuccessRate => width / 100.0,
+            PrecisionTarget::MeanTime => width,
+        },
+        max_trials: batches.max_trials,
+    });
+    Trials {
+        count: batches.trials,
+        seed: scenario.seed.unwrap(),
+        threads: batches.threads.unwrap(),
+        precision,
+    }
+}
+
+fn simulate(args: &SimulateArgs) {
+    let scenario = &args.scenario;
+    let result = with_fight(scenario, |_, player, hunllef| {
+        hunllef::run_simulation(
+            &trials(scenario, &args.batches),
+            player,
+            hunllef,
+            scenario.eat_at_hp,
+            scenario.tick_eat,
+            scenario.max_time,
+        )
+    });
+
+    //the parameters as run, with the seed and thread count filled in
+    let parameters = serde_json::to_value(args).unwrap();
+    let summary = output::summary(&result, args.batches.histogram);
+    match scenario.output {
+        Output::Text => print_results(&result, scenario, &args.batches),
+        Output::Json => output::print_json(&json!({
+            "parameters": parameters,
+            "result": summary,
+        })),
+        Output::Csv => output::print_csv(&parameters, &[json!({ "result": summary })]),
+    }
+}
+
+//Runs each value of the swept option on the same seed
+fn sweep(args: &SweepArgs) {
+    if let Some(limits) = args.over.limits() {
+        for (flag, value) in [("from", args.from), ("to", args.to)] {
+            match value {
+                Some(value) if !limits.contains(&value) => Cli::command()
+                    .error(
+                        ErrorKind::ValueValidation,
+                        format!(
+                            "--{flag} {value} is outside {}-{} for --over {}",
+                            limits.start(),
+                            limits.end(),
+                            args.over.to_possible_value().unwrap().get_name()
+                        ),
+                    )
+                    .exit(),
+                _ => {}
+            }
         }
-    } else {
-        let (success, fish_eaten, times) = hunllef::run_simulation(
-            args.trials,
-            &player,
-            &hunllef,
-            args.eat_at_hp,
-            args.tick_eat,
-            args.max_time,
-        );
+    }
+
+    let (from, to) = args.over.range(&args.scenario);
+    let values =
+        (args.from.unwrap_or(from)..=args.to.unwrap_or(to)).step_by(args.step.max(1) as usize);
+    let sweep: Vec<_> = values
+        .map(|value| {
+            let mut scenario = args.scenario.clone();
+            args.over.set(&mut scenario, value);
+            let result = with_fight(&scenario, |_, player, hunllef| {
+                hunllef::run_simulation(
+                    &trials(&scenario, &args.batches),
+                    player,
+                    hunllef,
+                    scenario.eat_at_hp,
+                    scenario.tick_eat,
+                    scenario.max_time,
+                )
+            });
+            (value, result)
+        })
+        .collect();
+
+    let parameters = serde_json::to_value(args).unwrap();
+    let name = args.over.name();
+    let rows: Vec<_> = sweep
+        .iter()
+        .map(|(value, result)| {
+            json!({ name: value, "result": output::summary(result, args.batches.histogram) })
+        })
+        .collect();
+    match args.scenario.output {
+        Output::Text => {
+            for (value, result) in &sweep {
+                let (low, high) = result.success_interval();
+                println!(
+                    "{name} {value} ({} trials): {:.2}% (95% CI {:.2}-{:.2}%), avg time {:.1} ticks (se {:.2}), avg fish eaten {:.1} (se {:.2})",
+                    result.trials,
+                    result.success_rate() * 100.0,
+                    low * 100.0,
+                    high * 100.0,
+                    result.mean_time(),
+                    result.time_std_error(),
+                    result.mean_fish(),
+                    result.fish_std_error()
+                );
+            }
+        }
+        Output::Json => output::print_json(&json!({ "parameters": parameters, "sweep": rows })),
+        Output::Csv => output::print_csv(&parameters, &rows),
+    }
+}
+
+fn optimise(args: &OptimiseArgs) {
+    let scenario = &args.scenario;
+    let trials = trials(scenario, &args.batches);
+    let histogram = args.batches.histogram;
+    let parameters = serde_json::to_value(args).unwrap();
+
+    if let Some(target) = args.min_fish {
+        let found = with_fight(scenario, |_, player, hunllef| {
+            hunllef::min_fish(
+                &trials,
+                target / 100.0,
+                player,
+                hunllef,
+                scenario.eat_at_hp,
+                scenario.tick_eat,
+                scenario.max_time,
+            )
+        });
+        let row = json!({
+            "min_fish": found.as_ref().map(|(fish, _)| fish),
+            "result": found.as_ref().map(|(_, result)| output::summary(result, histogram)),
+        });
+        match (scenario.output, found) {
+            (Output::Text, Some((fish, result))) => {
+                println!("min fish for {target:.2}% success: {fish}");
+                print_results(&result, scenario, &args.batches);
+            }
+            (Output::Text, None) => println!(
+                "{target:.2}% success not reached with {} fish (seed {})",
+                scenario.fish, trials.seed
+            ),
+            (Output::Json, _) => {
+                output::print_json(&json!({ "parameters": parameters, "search": row }))
+            }
+            (Output::Csv, _) => output::print_csv(&parameters, &[row]),
+        }
+        return;
+    }
+
+    //clap requires one of --objective and --min-fish
+    let objective = args.objective.unwrap();
+    let strategies = eat_strategies(args);
+    if strategies.is_empty() {
+        Cli::command()
+            .error(
+                ErrorKind::ValueValidation,
+                format!(
+                    "--eat-step {} leaves no eat thresholds up to the HP level ({})",
+                    args.eat_step, scenario.hp
+                ),
+            )
+            .exit();
+    }
+    let ranked = with_fight(scenario, |_, player, hunllef| {
+        hunllef::optimise_eating(
+            &trials,
+            objective,
+            &strategies,
+            player,
+            hunllef,
+            scenario.max_time,
+        )
+    });
+    let rows: Vec<_> = ranked
+        .iter()
+        .map(|(strategy, result)| {
+            json!({ "strategy": strategy, "result": output::summary(result, histogram) })
+        })
+        .collect();
+    match scenario.output {
+        Output::Text => {
+            let (best, result) = &ranked[0];
+            println!("best: {}", describe_strategy(best));
+            print_results(result, scenario, &args.batches);
+            println!();
+            for (strategy, result) in &ranked {
+                println!(
+                    "{}: {:.2}% success, avg fish eaten {:.2} ({:.2} in successes), avg time {:.1} ticks",
+                    describe_strategy(strategy),
+                    result.success_rate() * 100.0,
+                    result.mean_fish(),
+                    result.mean_success_fish(),
+                    result.mean_time()
+                );
+            }
+        }
+        Output::Json => output::print_json(&json!({ "parameters": parameters, "ranking": rows })),
+        Output::Csv => output::print_csv(&parameters, &rows),
+    }
+}
+
+fn trace(args: &TraceArgs) {
+    let scenario = &args.scenario;
+    let seed = scenario.seed.unwrap();
+    let fights = with_fight(scenario, |_, player, hunllef| {
+        hunllef::trace_fights(
+            args.fights,
+            player,
+            hunllef,
+            scenario.eat_at_hp,
+            scenario.tick_eat,
+            scenario.max_time,
+            seed,
+        )
+    });
+
+    let parameters = serde_json::to_value(args).unwrap();
+    match scenario.output {
+        Output::Text => print_log(&fights, seed),
+        Output::Json => output::print_json(&json!({
+            "parameters": parameters,
+            "fights": fights
+                .iter()
+                .enumerate()
+                .map(|(n, (fight, events))| output::trace(n + 1, fight, events))
+                .collect::<Vec<_>>(),
+        })),
+        Output::Csv => {
+            let rows: Vec<_> = fights
+                .iter()
+                .enumerate()
+                .flat_map(|(n, (_, events))| output::trace_rows(n + 1, events))
+                .collect();
+            output::print_csv(&parameters, &rows);
+        }
+    }
+}
+
+fn compare(args: &CompareArgs) {
+    let scenario = &args.scenario;
+    let allowed = |loadout: &Loadout| {
+        let tier_allowed = args
+            .only_tier
+            .iter()
+            .all(|&(weapon, tier)| loadout.weapon != weapon || loadout.tier == tier);
+        tier_allowed && !args.exclude_prayer.contains(&loadout.prayer)
+    };
+    let pairs: Vec<_> = hunllef::loadout_pairs(scenario.rotation != Rotation::SixSix)
+        .into_iter()
+        .filter(|(setup1, setup2)| allowed(setup1) && allowed(setup2))
+        .collect();
+    let ranked = with_fight(scenario, |tables, player, hunllef| {
+        hunllef::compare_setups(
+            &trials(scenario, &args.batches),
+            args.objective,
+            &pairs,
+            tables,
+            scenario.armour,
+            player,
+            hunllef,
+            scenario.eat_at_hp,
+            scenario.tick_eat,
+            scenario.max_time,
+        )
+    });
+
+    let parameters = serde_json::to_value(args).unwrap();
+    let rows: Vec<_> = ranked
+        .iter()
+        .map(|((setup1, setup2), result)| {
+            json!({
+                "setup1": setup1,
+                "setup2": setup2,
+                "result": output::summary(result, args.batches.histogram),
+            })
+        })
+        .collect();
+    match scenario.output {
+        Output::Text => {
+            for ((setup1, setup2), result) in &ranked {
+                let (low, high) = result.success_interval();
+                println!(
+                    "{} + {}: {:.2}% (95% CI {:.2}-{:.2}%), avg fish eaten {:.2} ({:.2} in successes), avg time {:.1} ticks",
+                    describe_loadout(setup1),
+                    describe_loadout(setup2),
+                    result.success_rate() * 100.0,
+                    low * 100.0,
+                    high * 100.0,
+                    result.mean_fish(),
+                    result.mean_success_fish(),
+                    result.mean_time()
+                );
+            }
+        }
+        Output::Json => output::print_json(&json!({ "parameters": parameters, "ranking": rows })),
+        Output::Csv => output::print_csv(&parameters, &rows),
+    }
+}
+
+//Runs each scenario of a profile as a normal simulation and compares them
+fn run_profile(args: &ProfileRunArgs, matches: &ArgMatches) {
+    let name = &args.name;
+    let profiles: Profiles = load(&args.profiles, "profiles", |toml| {
+        toml::from_str(toml).map_err(|e| e.to_string())
+    });
+    let Some(profile) = profiles.profile.into_iter().find(|p| &p.name == name) else {
+        eprintln!("error: no profile '{name}' in {}", args.profiles.display());
+        process::exit(1);
+    };
+
+    //every scenario is checked before any are run, so a mistake in the last
+    //one doesn't come after waiting on the rest
+    let scenarios: Vec<_> = profile
+        .scenario
+        .iter()
+        .map(|scenario| {
+            let mut options = toml::Table::try_from(profile.levels).unwrap();
+            options.extend(scenario.options.clone());
+            let args = apply_config(args, options, matches)
+                .and_then(|args| validate(&args.scenario).map(|_| args))
+                .unwrap_or_else(|e| {
+                    eprintln!("error: invalid scenario '{}': {e}", scenario.name);
+                    process::exit(1);
+                });
+            (&scenario.name, args)
+        })
+        .collect();
+
+    let rows: Vec<_> = scenarios
+        .into_iter()
+        .map(|(name, args)| {
+            let result = with_fight(&args.scenario, |_, player, hunllef| {
+                hunllef::run_simulation(
+                    &trials(&args.scenario, &args.batches),
+                    player,
+                    hunllef,
+                    args.scenario.eat_at_hp,
+                    args.scenario.tick_eat,
+                    args.scenario.max_time,
+                )
+            });
+            (name, args, result)
+        })
+        .collect();
+
+    match args.scenario.output {
+        Output::Text => {
+            let width = rows.iter().map(|(name, ..)| name.len()).max().unwrap_or(0);
+            println!("profile: {name}");
+            println!(
+                "{:width$}  {:>7}  {:>26}  {:>8}  {:>8}",
+                "scenario", "trials", "success rate (95% CI)", "avg fish", "avg time"
+            );
+            for (name, _, result) in &rows {
+                let (low, high) = result.success_interval();
+                println!(
+                    "{name:width$}  {:>7}  {:>26}  {:>8.2}  {:>8.1}",
+                    result.trials,
+                    format!(
+                        "{:.2}% ({:.2}-{:.2}%)",
+                        result.success_rate() * 100.0,
+                        low * 100.0,
+                        high * 100.0
+                    ),
+                    result.mean_fish(),
+                    result.mean_time()
+                );
+            }
+        }
+        Output::Json | Output::Csv => {
+            let rows: Vec<_> = rows
+                .iter()
+                .map(|(name, args, result)| {
+                    json!({
+                        "scenario": name,
+                        "parameters": args,
+                        "result": output::summary(result, args.batches.histogram),
+                    })
+                })
+                .collect();
+            let parameters = json!({ "profile": name, "levels": profile.levels });
+            if args.scenario.output == Output::Json {
+                output::print_json(&json!({ "parameters": parameters, "scenarios": rows }));
+            } else {
+                output::print_csv(&parameters, &rows);
+            }
+        }
+    }
+}
 
-        let success_rate = (success as f32 * 100.0) / (args.trials as f32);
-        let avg_fish: f64 = fish_eaten.iter().sum::<u64>() as f64 / fish_eaten.len() as f64;
-        let avg_time = times.iter().map(|t| *t as u64).sum::<u64>() as f64 / times.len() as f64;
-        println!("success rate: {:.2}%", success_rate);
-        println!("avg fish eaten: {:.1}", avg_fish);
-        println!("avg time: {:.1} ticks", avg_time);
+fn main() {
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let (_, matches) = matches.subcommand().unwrap();
 
-        if args.histogram {
-            generate_histogram(&times, &fish_eaten);
+    match cli.command {
+        Command::Simulate(args) => simulate(&prepare(args, matches)),
+        Command::Sweep(args) => sweep(&prepare(args, matches)),
+        Command::Optimise(args) => optimise(&prepare(args, matches)),
+        Command::Trace(args) => trace(&prepare(args, matches)),
+        Command::Compare(args) => compare(&prepare(args, matches)),
+        Command::Profile {
+            action: ProfileAction::Run(args),
+        } => {
+            //each scenario sets its own options, so there's no one config to dump
+            if args.scenario.dump_config {
+                Cli::command()
+                    .error(
+                        ErrorKind::ArgumentConflict,
+                        "--dump-config can't be used with `profile run`",
+                    )
+                    .exit();
+            }
+            let (_, matches) = matches.subcommand().unwrap();
+            run_profile(&prepare(args, matches), matches)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Parses a simulate command line, returning its args and matches
+    fn simulate_args(args: &[&str]) -> (SimulateArgs, ArgMatches) {
+        let matches = Cli::command()
+            .try_get_matches_from(["hunllef", "simulate"].iter().chain(args))
+            .unwrap();
+        let (_, matches) = matches.subcommand().unwrap();
+        let args = SimulateArgs::from_arg_matches(matches).unwrap();
+        (args, matches.clone())
+    }
+
+    fn config(toml: &str) -> toml::Table {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn config_merges_under_command_line() {
+        let (args, matches) = simulate_args(&["--fish", "5", "--trials", "10"]);
+        let merged = apply_config(
+            &args,
+            config("fish = 8\narmour = 2\nsetup1 = 'halberd'\ntrials = 20\nhistogram = true"),
+            &matches,
+        )
+        .unwrap();
+        //given on the command line
+        assert_eq!(merged.scenario.fish, 5);
+        assert_eq!(merged.batches.trials, 10);
+        //defaults are overridden
+        assert_eq!(merged.scenario.armour, 2);
+        assert!(matches!(merged.scenario.setup1, Weapon::Halberd));
+        assert!(merged.batches.histogram);
+        //and the rest are left alone
+        assert_eq!(merged.scenario.setup1_tier, 3);
+        assert!(matches!(merged.scenario.setup2, Weapon::Staff));
+
+        //a default given on the command line still wins
+        let (args, matches) = simulate_args(&["--fish", "12"]);
+        let merged = apply_config(&args, config("fish = 8"), &matches).unwrap();
+        assert_eq!(merged.scenario.fish, 12);
+    }
+
+    #[test]
+    fn config_skips_other_subcommands_options() {
+        let (args, matches) = simulate_args(&[]);
+        let merged = apply_config(&args, config("over = 'armour'\nfish = 3"), &matches).unwrap();
+        assert_eq!(merged.scenario.fish, 3);
+    }
+
+    #[test]
+    fn config_errors() {
+        let (args, matches) = simulate_args(&[]);
+        let error = |toml| apply_config(&args, config(toml), &matches).unwrap_err();
+        assert_eq!(error("fihs = 3"), "unknown option 'fihs'");
+        assert_eq!(
+            error("config = 'other.toml'"),
+            "'config' can't be set from a file"
+        );
+        assert_eq!(
+            error("dump_config = true"),
+            "'dump_config' can't be set from a file"
+        );
+        assert!(error("fish = 'lots'").contains("invalid type"));
+        assert!(error("setup1 = 'sword'").contains("sword"));
+    }
+
+    #[test]
+    fn config_values_are_validated() {
+        let (args, matches) = simulate_args(&[]);
+        let merged = apply_config(&args, config("stomp_rate = 1.5"), &matches).unwrap();
+        assert_eq!(
+            validate(&merged.scenario).unwrap_err(),
+            "invalid value for '--stomp-rate': must be between 0 and 1, found 1.5"
+        );
+
+        let merged = apply_config(
+            &args,
+            config("tornado_min_damage = 20\ntornado_max_damage = 10"),
+            &matches,
+        )
+        .unwrap();
+        assert!(validate(&merged.scenario).is_err());
+        assert!(validate(&args.scenario).is_ok());
+    }
+}
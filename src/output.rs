@@ -0,0 +1,242 @@
+//! Machine-readable (JSON and CSV) output of results
+
+use clap::ValueEnum;
+use hunllef::{Event, Failure, Fight, SimulationResult, Stats};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Output {
+    Text,
+    Json,
+    Csv,
+}
+
+//matches the percentiles of the text histogram
+const PERCENTILES: [f64; 7] = [0.5, 2.5, 16.7, 50.0, 83.0, 97.5, 99.5];
+
+#[derive(Serialize)]
+struct FailureSummary {
+    count: u64,
+    mean_hunllef_hp: f64, //NaN (null) when there were none
+}
+
+impl FailureSummary {
+    fn new(hunllef_hp: &Stats) -> Self {
+        FailureSummary {
+            count: hunllef_hp.count(),
+            mean_hunllef_hp: hunllef_hp.mean(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Failures {
+    out_of_fish: FailureSummary,
+    died_with_fish: FailureSummary,
+    out_of_prayer: FailureSummary,
+    max_time: FailureSummary,
+}
+
+#[derive(Serialize)]
+struct Interval {
+    low: f64,
+    high: f64,
+}
+
+#[derive(Serialize)]
+struct Summary {
+    trials: u32,
+    success: u32,
+    success_rate: f64,
+    success_interval: Interval, //95% Wilson score
+    mean_time: f64,
+    time_std_error: f64,
+    median_time: u64,
+    mean_fish: f64,
+    fish_std_error: f64,
+    median_fish: u64,
+    mean_success_fish: f64,
+    mean_off_prayer_hits: f64,
+    mean_doses_drunk: f64,
+    failures: Failures,
+}
+
+fn percentiles(stats: &Stats) -> Value {
+    let hist = stats.histogram();
+    PERCENTILES
+        .iter()
+        .map(|p| (format!("p{p}"), json!(hist.value_at_percentile(*p))))
+        .collect::<Map<_, _>>()
+        .into()
+}
+
+/// Summary of a simulation result, with time (ticks) and fish eaten
+/// percentiles if `histogram` is set
+pub fn summary(result: &SimulationResult, histogram: bool) -> Value {
+    let failures = &result.failures;
+    let (low, high) = result.success_interval();
+    let summary = Summary {
+        trials: result.trials,
+        success: result.success,
+        success_rate: result.success_rate(),
+        success_interval: Interval { low, high },
+        mean_time: result.mean_time(),
+        time_std_error: result.time_std_error(),
+        median_time: result.median_time(),
+        mean_fish: result.mean_fish(),
+        fish_std_error: result.fish_std_error(),
+        median_fish: result.median_fish(),
+        mean_success_fish: result.mean_success_fish(),
+        mean_off_prayer_hits: result.off_prayer_hits.mean(),
+        mean_doses_drunk: result.doses_drunk.mean(),
+        failures: Failures {
+            out_of_fish: FailureSummary::new(failures.cause(Failure::OutOfFish)),
+            died_with_fish: FailureSummary::new(failures.cause(Failure::DiedWithFish)),
+            out_of_prayer: FailureSummary::new(failures.cause(Failure::OutOfPrayer)),
+            max_time: FailureSummary::new(failures.cause(Failure::MaxTime)),
+        },
+    };
+
+    let mut summary = serde_json::to_value(summary).unwrap();
+    if histogram {
+        summary["histogram"] = json!({
+            "time": percentiles(&result.time),
+            "fish_eaten": percentiles(&result.fish_eaten),
+        });
+    }
+    summary
+}
+
+/// A traced fight with its result, failure cause and events
+pub fn trace(n: usize, fight: &Fight, events: &[(u16, Event)]) -> Value {
+    json!({
+        "fight": n,
+        "result": fight,
+        "failure": fight.failure(),
+        "events": events
+            .iter()
+            .map(|(time, event)| json!({ "time": time, "event": event }))
+            .collect::<Vec<_>>(),
+    })
+}
+
+/// One row per event of a traced fight
+pub fn trace_rows(n: usize, events: &[(u16, Event)]) -> Vec<Value> {
+    events
+        .iter()
+        .map(|(time, event)| json!({ "fight": n, "time": time, "event": event }))
+        .collect()
+}
+
+pub fn print_json(document: &Value) {
+    println!("{}", serde_json::to_string_pretty(document).unwrap());
+}
+
+/// Prints rows as CSV, each prefixed with the parameters. Nested fields are
+/// flattened into dotted column names, and the columns are every field seen
+/// in any row so rows of different shapes share one header.
+pub fn print_csv(parameters: &Value, rows: &[Value]) {
+    let rows: Vec<Vec<(String, String)>> = rows
+        .iter()
+        .map(|row| {
+            let mut fields = Vec::new();
+            flatten("parameters", parameters, &mut fields);
+            flatten("", row, &mut fields);
+            fields
+        })
+        .collect();
+
+    let mut columns: Vec<&str> = Vec::new();
+    for (column, _) in rows.iter().flatten() {
+        if !columns.contains(&column.as_str()) {
+            columns.push(column);
+        }
+    }
+
+    let header: Vec<String> = columns.iter().map(|c| escape(c)).collect();
+    println!("{}", header.join(","));
+    for row in &rows {
+        let line: Vec<String> = columns
+            .iter()
+            .map(|column| {
+                row.iter()
+                    .find(|(c, _)| c == column)
+                    .map_or(String::new(), |(_, value)| escape(value))
+            })
+            .collect();
+        println!("{}", line.join(","));
+    }
+}
+
+fn flatten(prefix: &str, value: &Value, fields: &mut Vec<(String, String)>) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                let key = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{prefix}.{key}")
+                };
+                flatten(&key, value, fields);
+            }
+        }
+        Value::Null => fields.push((prefix.to_string(), String::new())),
+        Value::String(s) => fields.push((prefix.to_string(), s.clone())),
+        value => fields.push((prefix.to_string(), value.to_string())),
+    }
+}
+
+fn escape(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flatten_nested() {
+        let mut fields = Vec::new();
+        let value = json!({
+            "trials": 10,
+            "time": { "mean": 1.5, "max": null },
+            "failures": { "food": { "count": 2 } },
+            "weapon": "bow",
+        });
+        flatten("result", &value, &mut fields);
+        let fields: Vec<_> = fields
+            .iter()
+            .map(|(c, v)| (c.as_str(), v.as_str()))
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                ("result.trials", "10"),
+                ("result.time.mean", "1.5"),
+                ("result.time.max", ""),
+                ("result.failures.food.count", "2"),
+                ("result.weapon", "bow"),
+            ]
+        );
+
+        //rows are flattened without a prefix
+        let mut fields = Vec::new();
+        flatten("", &json!({ "scenario": "base" }), &mut fields);
+        assert_eq!(fields, [("scenario".to_string(), "base".to_string())]);
+    }
+
+    #[test]
+    fn escape_fields() {
+        assert_eq!(escape("bow"), "bow");
+        assert_eq!(escape(""), "");
+        assert_eq!(escape("1,2"), "\"1,2\"");
+        assert_eq!(escape("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(escape("a\nb"), "\"a\nb\"");
+    }
+}
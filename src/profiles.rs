@@ -0,0 +1,94 @@
+//! Teammates' levels and the scenarios they run, for `profile run`
+
+use hunllef::Levels;
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profiles {
+    #[serde(default)]
+    pub profile: Vec<Profile>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub name: String,
+    pub levels: Levels,
+    #[serde(default)]
+    pub scenario: Vec<Scenario>,
+}
+
+/// Options (named as in --dump-config) applied on top of the profile's levels
+#[derive(Debug, Deserialize)]
+pub struct Scenario {
+    pub name: String,
+    #[serde(flatten)]
+    pub options: toml::Table,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROFILES: &str = r#"
+[[profile]]
+name = "sam"
+levels = { attack = 90, strength = 90, defence = 85, ranged = 92, magic = 94, hp = 90, prayer = 77 }
+
+[[profile.scenario]]
+name = "t3 bow/staff"
+fish = 12
+
+[[profile.scenario]]
+name = "t2 halberd/staff, armour 2"
+setup1 = "halberd"
+setup1_tier = 2
+
+[[profile]]
+name = "alex"
+levels = { attack = 99, strength = 99, defence = 99, ranged = 99, magic = 99, hp = 99, prayer = 99 }
+"#;
+
+    #[test]
+    fn parse_profiles() {
+        let profiles: Profiles = toml::from_str(PROFILES).unwrap();
+        let [sam, alex] = &profiles.profile[..] else {
+            panic!("expected 2 profiles, found {}", profiles.profile.len());
+        };
+        assert_eq!(sam.name, "sam");
+        assert_eq!(sam.levels.ranged, 92);
+        assert_eq!(sam.levels.prayer, 77);
+
+        //scenario options are kept as given, for apply_config to check
+        let names: Vec<_> = sam.scenario.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["t3 bow/staff", "t2 halberd/staff, armour 2"]);
+        assert_eq!(sam.scenario[0].options, toml::toml! { fish = 12 });
+        assert_eq!(
+            sam.scenario[1].options,
+            toml::toml! { setup1 = "halberd" setup1_tier = 2 }
+        );
+        assert!(!sam.scenario[1].options.contains_key("name"));
+
+        //scenarios are optional
+        assert!(alex.scenario.is_empty());
+        assert!(toml::from_str::<Profiles>("").unwrap().profile.is_empty());
+    }
+
+    #[test]
+    fn reject_bad_profiles() {
+        let error = |toml: &str| toml::from_str::<Profiles>(toml).unwrap_err().to_string();
+        assert!(error("[[profile]]\nname = \"sam\"").contains("missing field `levels`"));
+        assert!(
+            error(&format!("{PROFILES}\n[[team]]\nname = \"x\"")).contains("unknown field `team`")
+        );
+        assert!(error(&PROFILES.replace("levels", "level")).contains("unknown field `level`"));
+        assert!(
+            error("[[profile]]\nname = \"sam\"\nlevels = { attack = 90 }")
+                .contains("missing field `strength`")
+        );
+        //every scenario needs a name to be listed under
+        assert!(error(&PROFILES.replace("name = \"t3 bow/staff\"", ""))
+            .contains("missing field `name`"));
+    }
+}
@@ -0,0 +1,134 @@
+//! Equipment, prayer and boss stats, bundled from tables.toml and optionally
+//! overridden from another file so rebalances don't need a recompile
+
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{BossVariant, Prayer, Weapon};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ArmourStats {
+    pub tier: u8,
+    pub accuracy: u16,
+    pub defence: u16,
+    pub prayer_bonus: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WeaponStats {
+    pub weapon: Weapon,
+    pub tier: u8,
+    pub accuracy: u16,
+    pub strength: u16, //max hit for the staff
+}
+
+/// Percentage bonuses of a prayer, and its drain effect alongside a
+/// protection prayer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PrayerStats {
+    pub prayer: Prayer,
+    pub accuracy: u16,
+    pub strength: u16,
+    pub defence: u16,
+    pub magic_defence: u16,
+    pub drain_effect: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BossStats {
+    pub variant: BossVariant,
+    pub hp: u16,
+    pub level: u16, //all combat levels
+    pub attack_bonus: u16,
+    pub defence_bonus: u16,
+    pub max_hit: Vec<u16>, //through protection prayer, by armour tier
+    pub off_prayer_max_hit: u16,
+    pub stomp_max_hit: u16,
+}
+
+/// Every stat the simulator looks up rather than derives
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StatTables {
+    #[serde(default)]
+    pub armour: Vec<ArmourStats>,
+    #[serde(default)]
+    pub weapon: Vec<WeaponStats>,
+    #[serde(default)]
+    pub prayer: Vec<PrayerStats>,
+    #[serde(default)]
+    pub boss: Vec<BossStats>,
+}
+
+impl StatTables {
+    /// The tables compiled into the simulator
+    pub fn bundled() -> &'static StatTables {
+        static BUNDLED: OnceLock<StatTables> = OnceLock::new();
+        BUNDLED.get_or_init(|| {
+            StatTables::from_toml(include_str!("tables.toml")).expect("bundled tables are valid")
+        })
+    }
+
+    pub fn from_toml(toml: &str) -> Result<StatTables, toml::de::Error> {
+        toml::from_str(toml)
+    }
+
+    /// Replaces rows with the same key as a row in `other`, and adds the rest
+    pub fn merge(&mut self, other: StatTables) {
+        fn merge_rows<T, K: PartialEq>(rows: &mut Vec<T>, other: Vec<T>, key: impl Fn(&T) -> K) {
+            for row in other {
+                match rows.iter_mut().find(|r| key(r) == key(&row)) {
+                    Some(existing) => *existing = row,
+                    None => rows.push(row),
+                }
+            }
+        }
+
+        merge_rows(&mut self.armour, other.armour, |r| r.tier);
+        merge_rows(&mut self.weapon, other.weapon, |r| (r.weapon, r.tier));
+        merge_rows(&mut self.prayer, other.prayer, |r| r.prayer);
+        merge_rows(&mut self.boss, other.boss, |r| r.variant);
+    }
+
+    /// No armour (or an unknown tier) gives no bonuses
+    pub fn armour(&self, tier: u8) -> ArmourStats {
+        self.armour
+            .iter()
+            .find(|a| a.tier == tier)
+            .copied()
+            .unwrap_or(ArmourStats {
+                tier,
+                accuracy: 0,
+                defence: 0,
+                prayer_bonus: 0,
+            })
+    }
+
+    pub fn weapon(&self, weapon: Weapon, tier: u8) -> WeaponStats {
+        self.weapon
+            .iter()
+            .find(|w| w.weapon == weapon && w.tier == tier)
+            .copied()
+            .unwrap_or_else(|| panic!("no stats for tier {tier} {weapon:?}"))
+    }
+
+    pub fn prayer(&self, prayer: Prayer) -> PrayerStats {
+        self.prayer
+            .iter()
+            .find(|p| p.prayer == prayer)
+            .copied()
+            .unwrap_or_else(|| panic!("no stats for {prayer:?}"))
+    }
+
+    pub fn boss(&self, variant: BossVariant) -> &BossStats {
+        self.boss
+            .iter()
+            .find(|b| b.variant == variant)
+            .unwrap_or_else(|| panic!("no stats for the {variant:?} Hunllef"))
+    }
+}